@@ -0,0 +1,87 @@
+use crate::*;
+
+#[near_bindgen]
+impl Contract {
+    pub fn get_admins(&self, from_index: Option<u64>, limit: Option<u64>) -> Vec<AccountId> {
+        Contract::paginate(&self.admins, from_index, limit)
+    }
+
+    pub fn get_denylist(&self, from_index: Option<u64>, limit: Option<u64>) -> Vec<AccountId> {
+        Contract::paginate(&self.denylist, from_index, limit)
+    }
+
+    pub fn get_plan_allowlist(
+        &self,
+        ft_account_id: AccountId,
+        staking_plan: String,
+        from_index: Option<u64>,
+        limit: Option<u64>,
+    ) -> Vec<AccountId> {
+        self.plan_allowlists
+            .get(&(ft_account_id, staking_plan))
+            .map_or(Vec::new(), |allowlist| Contract::paginate(&allowlist, from_index, limit))
+    }
+
+    pub fn is_denied(&self, account_id: AccountId) -> bool {
+        self.denylist.contains(&account_id)
+    }
+
+    //accounts can stake in plans without allowlist_only, or when they are on the allowlist of the plan
+    pub fn is_allowed(
+        &self,
+        account_id: AccountId,
+        ft_account_id: AccountId,
+        staking_plan: String,
+    ) -> bool {
+        let apy = self.internal_get_apy(&ft_account_id, &staking_plan);
+        !self.denylist.contains(&account_id) && self.internal_is_allowed(&account_id, &ft_account_id, &apy)
+    }
+}
+
+impl Contract {
+    pub(crate) fn paginate(
+        set: &UnorderedSet<AccountId>,
+        from_index: Option<u64>,
+        limit: Option<u64>,
+    ) -> Vec<AccountId> {
+        set.iter()
+            .skip(from_index.unwrap_or(0) as usize)
+            .take(limit.unwrap_or(set.len()) as usize)
+            .collect()
+    }
+
+    pub(crate) fn internal_is_allowed(
+        &self,
+        account_id: &AccountId,
+        ft_account_id: &AccountId,
+        apy: &APY,
+    ) -> bool {
+        !apy.allowlist_only
+            || self
+                .plan_allowlists
+                .get(&(ft_account_id.clone(), apy.apy_key.clone()))
+                .is_some_and(|allowlist| allowlist.contains(account_id))
+    }
+
+    //checks everything that decides if an account can put tokens into a plan
+    pub(crate) fn assert_can_stake(&self, account_id: &AccountId, ft_account_id: &AccountId, apy: &APY) {
+        self.assert_not_denied(account_id);
+        assert!(
+            self.internal_is_allowed(account_id, ft_account_id, apy),
+            "Only allowlisted accounts can stake in this plan"
+        );
+        self.assert_plan_eligible(account_id, apy);
+    }
+
+    pub(crate) fn assert_not_denied(&self, account_id: &AccountId) {
+        assert!(!self.denylist.contains(account_id), "Account is denied");
+    }
+
+    pub(crate) fn assert_admin(&self) {
+        let caller = env::predecessor_account_id();
+        assert!(
+            self.is_owner() || self.admins.contains(&caller),
+            "Admin's method"
+        );
+    }
+}
@@ -0,0 +1,229 @@
+use crate::*;
+
+use crate::ft_calls::{ext_ft, this_contract, BASE_GAS};
+use near_contract_standards::fungible_token::metadata::{FungibleTokenMetadata, FT_METADATA_SPEC};
+
+#[near_bindgen]
+impl Contract {
+    //stakes of this FT mint receipt tokens from now on, it can only be chosen once
+    pub fn set_receipt_token(&mut self, ft_contract_id: AccountId, decimals: u8) {
+        self.assert_owner();
+        assert!(
+            self.receipt_ft_account_id.is_none(),
+            "Receipt token is already configured"
+        );
+        let ft = self.approved_fts.get(&ft_contract_id).expect("FT is not approved");
+        let metadata = FungibleTokenMetadata {
+            spec: FT_METADATA_SPEC.to_string(),
+            name: format!("Staked {}", ft.symbol),
+            symbol: format!("st{}", ft.symbol),
+            icon: None,
+            reference: None,
+            reference_hash: None,
+            decimals,
+        };
+        metadata.assert_valid();
+        self.receipt_ft_account_id = Some(ft_contract_id);
+        self.receipt_metadata = Some(metadata);
+    }
+
+    //decimals of an FT approved without them, its stakes count toward tiers from now on
+    pub fn set_ft_decimals(&mut self, ft_account_id: AccountId, decimals: u8) {
+        self.assert_owner();
+        Contract::assert_valid_decimals(Some(decimals));
+        let mut ft = self.approved_fts.get(&ft_account_id).expect("FT is not approved");
+        ft.decimals = Some(decimals);
+        self.approved_fts.insert(&ft_account_id, &ft);
+    }
+
+    //adds or replaces a staking plan of an approved FT
+    pub fn set_apy(&mut self, ft_account_id: AccountId, apy: APY) {
+        self.assert_owner();
+        self.internal_set_apy(&ft_account_id, apy);
+    }
+
+    pub fn approve_ft(&mut self, ft: FT, ft_apy: Vec<APY>) {
+        self.assert_owner();
+        self.internal_approve_ft(ft, ft_apy);
+    }
+
+    //stakers can propose plan changes once governance has a config, None turns it off
+    pub fn set_governance_config(&mut self, config: Option<GovernanceConfig>) {
+        self.assert_owner();
+        self.governance = config;
+    }
+
+    //tiers from the lowest to the highest, Ex: Bronze, Silver, Gold
+    pub fn set_tiers(&mut self, tiers: Vec<Tier>) {
+        self.assert_owner();
+        for pair in tiers.windows(2) {
+            assert!(
+                u128::from(pair[0].min_staked) < u128::from(pair[1].min_staked),
+                "Tiers must be sorted by min_staked"
+            );
+        }
+        for (index, tier) in tiers.iter().enumerate() {
+            assert!(
+                !tiers[..index].iter().any(|other| other.name == tier.name),
+                "Tier names must be unique"
+            );
+        }
+        self.tiers = tiers;
+    }
+
+    //only members of this tier and above can receive the airdrop of the FT
+    pub fn set_airdrop_tier(&mut self, ft_contract_id: AccountId, min_tier: Option<String>) {
+        self.assert_owner();
+        match min_tier {
+            Some(min_tier) => {
+                assert!(
+                    self.tiers.iter().any(|tier| tier.name == min_tier),
+                    "Unknown tier"
+                );
+                self.airdrop_tiers.insert(&ft_contract_id, &min_tier);
+            }
+            None => {
+                self.airdrop_tiers.remove(&ft_contract_id);
+            }
+        }
+    }
+
+    pub fn set_referral_config(&mut self, ft_account_id: AccountId, config: Option<ReferralConfig>) {
+        self.assert_owner();
+        match config {
+            Some(config) => {
+                assert!(config.reward_bps <= 10000, "Referral reward cannot be more than 100%");
+                self.referral_configs.insert(&ft_account_id, &config);
+            }
+            None => {
+                self.referral_configs.remove(&ft_account_id);
+            }
+        }
+    }
+
+    pub fn set_fee_config(&mut self, ft_account_id: AccountId, config: FeeConfig) {
+        self.assert_owner();
+        for fee in [
+            config.performance_fee_bps,
+            config.deposit_fee_bps,
+            config.withdrawal_fee_bps,
+            config.early_unstake_penalty_bps,
+        ] {
+            assert!(fee <= 10000, "Fees cannot be more than 100%");
+        }
+        //both are taken from the principal of an early unstake
+        assert!(
+            config.withdrawal_fee_bps as u32 + config.early_unstake_penalty_bps as u32 <= 10000,
+            "Withdrawal fee and early unstake penalty cannot be more than 100% together"
+        );
+        self.fee_configs.insert(&ft_account_id, &config);
+    }
+
+    pub fn set_treasurer(&mut self, treasurer: AccountId) {
+        self.assert_owner();
+        self.treasurer = treasurer;
+    }
+
+    pub fn withdraw_treasury(&mut self, token: AccountId, amount: U128) {
+        self.assert_treasurer();
+        let mut treasury = self.treasury.get(&token).expect("No fees collected for this token");
+        let balance = u128::from(treasury.balance);
+        assert!(
+            u128::from(amount) > 0 && u128::from(amount) <= balance,
+            "Cannot withdraw more than {} from the treasury",
+            balance
+        );
+        //taken out now and given back if the transfer fails
+        treasury.balance = U128::from(balance - u128::from(amount));
+        self.treasury.insert(&token, &treasury);
+
+        ext_ft::ft_transfer(
+            self.treasurer.clone(),
+            amount,
+            Some("Treasury".to_string()),
+            token.clone(),
+            1,
+            BASE_GAS,
+        )
+        .then(this_contract::treasury_callback(
+            token,
+            amount,
+            env::current_account_id(),
+            0,
+            BASE_GAS,
+        ));
+    }
+
+    pub fn add_admin(&mut self, account_id: AccountId) {
+        self.assert_owner();
+        self.admins.insert(&account_id);
+    }
+
+    pub fn remove_admin(&mut self, account_id: AccountId) {
+        self.assert_owner();
+        self.admins.remove(&account_id);
+    }
+
+    pub fn allow_accounts(
+        &mut self,
+        ft_account_id: AccountId,
+        staking_plan: String,
+        account_ids: Vec<AccountId>,
+    ) {
+        self.assert_admin();
+        self.internal_get_apy(&ft_account_id, &staking_plan);
+        let key = (ft_account_id, staking_plan);
+        let mut allowlist = self.plan_allowlists.get(&key).unwrap_or_else(|| {
+            let plan_hash = env::sha256(format!("{}:{}", key.0, key.1).as_bytes());
+            UnorderedSet::new(StorageKeys::PlanAllowlist { plan_hash })
+        });
+        for account_id in account_ids.iter() {
+            allowlist.insert(account_id);
+        }
+        self.plan_allowlists.insert(&key, &allowlist);
+    }
+
+    pub fn disallow_accounts(
+        &mut self,
+        ft_account_id: AccountId,
+        staking_plan: String,
+        account_ids: Vec<AccountId>,
+    ) {
+        self.assert_admin();
+        let key = (ft_account_id, staking_plan);
+        if let Some(mut allowlist) = self.plan_allowlists.get(&key) {
+            for account_id in account_ids.iter() {
+                allowlist.remove(account_id);
+            }
+            self.plan_allowlists.insert(&key, &allowlist);
+        }
+    }
+
+    //denied accounts cannot stake or claim, they can still unstake their principal
+    pub fn deny_accounts(&mut self, account_ids: Vec<AccountId>) {
+        self.assert_admin();
+        for account_id in account_ids.iter() {
+            self.denylist.insert(account_id);
+        }
+    }
+
+    pub fn undeny_accounts(&mut self, account_ids: Vec<AccountId>) {
+        self.assert_admin();
+        for account_id in account_ids.iter() {
+            self.denylist.remove(account_id);
+        }
+    }
+
+    //tokens emitted each second to the stakes of an emission plan of this FT, shared by their weight
+    pub fn set_emission_rate(&mut self, ft_account_id: AccountId, emission_per_second: U128) {
+        self.assert_owner();
+        assert!(
+            self.approved_ft_token_ids.contains(&ft_account_id),
+            "FT is not approved"
+        );
+        //rewards up to now are distributed with the previous rate
+        let mut pool = self.internal_get_emission_pool(&ft_account_id);
+        pool.emission_per_second = emission_per_second;
+        self.emission_pools.insert(&ft_account_id, &pool);
+    }
+}
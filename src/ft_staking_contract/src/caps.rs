@@ -0,0 +1,111 @@
+use crate::*;
+
+//what can still be staked, None when there is no cap
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RemainingCapacity {
+    pub plan: Option<U128>,
+    pub token: Option<U128>,
+    pub account: Option<U128>,
+}
+
+#[near_bindgen]
+impl Contract {
+    pub fn get_staked_total(&self, ft_account_id: AccountId, staking_plan: Option<String>) -> U128 {
+        U128::from(match staking_plan {
+            Some(staking_plan) => self.internal_plan_total(&ft_account_id, &staking_plan),
+            None => self.internal_token_total(&ft_account_id),
+        })
+    }
+
+    pub fn get_remaining_capacity(
+        &self,
+        ft_account_id: AccountId,
+        staking_plan: String,
+        account_id: Option<AccountId>,
+    ) -> RemainingCapacity {
+        let ft = self.approved_fts.get(&ft_account_id).expect("FT is not approved");
+        let apy = self.internal_get_apy(&ft_account_id, &staking_plan);
+        RemainingCapacity {
+            plan: apy.max_total_staked.map(|max| {
+                let staked = self.internal_plan_total(&ft_account_id, &staking_plan);
+                U128::from(u128::from(max).saturating_sub(staked))
+            }),
+            token: ft.max_total_staked.map(|max| {
+                let staked = self.internal_token_total(&ft_account_id);
+                U128::from(u128::from(max).saturating_sub(staked))
+            }),
+            account: account_id.and_then(|account_id| {
+                apy.max_per_account.map(|max| {
+                    let staked =
+                        self.internal_account_plan_total(&account_id, &ft_account_id, &staking_plan, None);
+                    U128::from(u128::from(max).saturating_sub(staked))
+                })
+            }),
+        }
+    }
+}
+
+impl Contract {
+    //principal an account has in a plan, leaving out a stake that is about to change
+    pub(crate) fn internal_account_plan_total(
+        &self,
+        account_id: &AccountId,
+        ft_account_id: &AccountId,
+        staking_plan: &str,
+        excluded: Option<StakeId>,
+    ) -> u128 {
+        let staked: u128 = self
+            .staking_history
+            .get(account_id)
+            .unwrap_or_default()
+            .iter()
+            .filter(|stake| {
+                &stake.ft_account_id == ft_account_id
+                    && stake.staking_plan == staking_plan
+                    && Some(stake.stake_id) != excluded
+            })
+            .map(|stake| u128::from(stake.amount))
+            .sum();
+        staked + u128::from(self.internal_get_position(account_id, ft_account_id, staking_plan).amount)
+    }
+
+    //how much more an account can stake in a plan before one of the caps is reached
+    pub(crate) fn internal_capacity(
+        &self,
+        account_id: &AccountId,
+        ft_account_id: &AccountId,
+        staking_plan: &str,
+        excluded: Option<StakeId>,
+    ) -> u128 {
+        let ft = self.approved_fts.get(ft_account_id).expect("FT is not approved");
+        let apy = self.internal_get_apy(ft_account_id, staking_plan);
+        let mut capacity = u128::MAX;
+        if let Some(max) = apy.max_total_staked {
+            let staked = self.internal_plan_total(ft_account_id, staking_plan);
+            capacity = min(capacity, u128::from(max).saturating_sub(staked));
+        }
+        if let Some(max) = ft.max_total_staked {
+            let staked = self.internal_token_total(ft_account_id);
+            capacity = min(capacity, u128::from(max).saturating_sub(staked));
+        }
+        if let Some(max) = apy.max_per_account {
+            let staked = self.internal_account_plan_total(account_id, ft_account_id, staking_plan, excluded);
+            capacity = min(capacity, u128::from(max).saturating_sub(staked));
+        }
+        capacity
+    }
+
+    //the part of a deposit that fits in the caps, the rest is returned to the sender
+    pub(crate) fn internal_accept_deposit(
+        &self,
+        account_id: &AccountId,
+        ft_account_id: &AccountId,
+        staking_plan: &str,
+        amount: u128,
+    ) -> u128 {
+        let accepted = min(amount, self.internal_capacity(account_id, ft_account_id, staking_plan, None));
+        assert!(accepted > 0, "Staking cap reached");
+        accepted
+    }
+}
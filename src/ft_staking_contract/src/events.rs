@@ -0,0 +1,66 @@
+use crate::*;
+use near_sdk::serde_json::{json, Value};
+
+pub(crate) const EVENT_STANDARD: &str = "ft_staking";
+pub(crate) const EVENT_VERSION: &str = "1.0.0";
+
+//logs a NEP-297 event so indexers and the webapp can follow stake changes
+pub(crate) fn emit_event(standard: &str, version: &str, event: &str, data: Value) {
+    let event = json!({
+        "standard": standard,
+        "version": version,
+        "event": event,
+        "data": [data],
+    });
+    log!("EVENT_JSON:{}", event);
+}
+
+pub(crate) fn emit_stake_transfer(stake_id: StakeId, old_owner_id: &AccountId, new_owner_id: &AccountId) {
+    emit_event(
+        EVENT_STANDARD,
+        EVENT_VERSION,
+        "stake_transfer",
+        json!({
+            "stake_id": stake_id,
+            "old_owner_id": old_owner_id,
+            "new_owner_id": new_owner_id,
+        }),
+    );
+}
+
+pub(crate) fn emit_nft_mint(owner_id: &AccountId, token_ids: Vec<String>) {
+    emit_event(
+        "nep171",
+        "1.0.0",
+        "nft_mint",
+        json!({
+            "owner_id": owner_id,
+            "token_ids": token_ids,
+        }),
+    );
+}
+
+pub(crate) fn emit_nft_transfer(old_owner_id: &AccountId, new_owner_id: &AccountId, token_ids: Vec<String>) {
+    emit_event(
+        "nep171",
+        "1.0.0",
+        "nft_transfer",
+        json!({
+            "old_owner_id": old_owner_id,
+            "new_owner_id": new_owner_id,
+            "token_ids": token_ids,
+        }),
+    );
+}
+
+pub(crate) fn emit_nft_burn(owner_id: &AccountId, token_ids: Vec<String>) {
+    emit_event(
+        "nep171",
+        "1.0.0",
+        "nft_burn",
+        json!({
+            "owner_id": owner_id,
+            "token_ids": token_ids,
+        }),
+    );
+}
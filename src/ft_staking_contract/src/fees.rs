@@ -0,0 +1,137 @@
+use crate::*;
+
+//fees of an FT in basis points, Ex: 2% = 200
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FeeConfig {
+    pub performance_fee_bps: u16, //taken from paid rewards
+    pub deposit_fee_bps: u16, //taken from staked principal
+    pub withdrawal_fee_bps: u16, //taken from unstaked principal
+    pub early_unstake_penalty_bps: u16, //taken from principal unstaked before the lock ends, 0 keeps early unstaking disabled
+}
+
+//fees taken from one payout, collected into the treasury once the transfer succeeds
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Fees {
+    pub ft_account_id: AccountId,
+    pub performance: U128,
+    pub deposit: U128,
+    pub withdrawal: U128,
+    pub penalty: U128,
+}
+
+//balance can be withdrawn by the treasurer, the other fields are totals since the start
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Treasury {
+    pub balance: U128,
+    pub performance: U128,
+    pub deposit: U128,
+    pub withdrawal: U128,
+    pub penalty: U128,
+}
+
+fn bps(amount: u128, bps: u16) -> u128 {
+    Contract::mul_div(amount, bps as u128, 10000)
+}
+
+fn add(a: U128, b: U128) -> U128 {
+    U128::from(u128::from(a) + u128::from(b))
+}
+
+impl Fees {
+    pub(crate) fn new(ft_account_id: &AccountId) -> Self {
+        Self {
+            ft_account_id: ft_account_id.clone(),
+            performance: U128::from(0),
+            deposit: U128::from(0),
+            withdrawal: U128::from(0),
+            penalty: U128::from(0),
+        }
+    }
+
+    pub(crate) fn total(&self) -> u128 {
+        u128::from(self.performance)
+            + u128::from(self.deposit)
+            + u128::from(self.withdrawal)
+            + u128::from(self.penalty)
+    }
+
+    pub(crate) fn on_deposit(&mut self, principal: u128, config: &FeeConfig) {
+        self.deposit = add(self.deposit, U128::from(bps(principal, config.deposit_fee_bps)));
+    }
+
+    pub(crate) fn on_withdrawal(&mut self, principal: u128, config: &FeeConfig, early: bool) {
+        self.withdrawal = add(self.withdrawal, U128::from(bps(principal, config.withdrawal_fee_bps)));
+        if early {
+            self.penalty = add(self.penalty, U128::from(bps(principal, config.early_unstake_penalty_bps)));
+        }
+    }
+
+    pub(crate) fn on_rewards(&mut self, reward: u128, config: &FeeConfig) {
+        self.performance = add(self.performance, U128::from(bps(reward, config.performance_fee_bps)));
+    }
+}
+
+impl Treasury {
+    pub(crate) fn new() -> Self {
+        Self {
+            balance: U128::from(0),
+            performance: U128::from(0),
+            deposit: U128::from(0),
+            withdrawal: U128::from(0),
+            penalty: U128::from(0),
+        }
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    pub fn get_fee_config(&self, ft_account_id: AccountId) -> FeeConfig {
+        self.internal_fee_config(&ft_account_id)
+    }
+
+    pub fn get_treasury(&self, ft_account_id: AccountId) -> Treasury {
+        self.treasury.get(&ft_account_id).unwrap_or_else(Treasury::new)
+    }
+
+    pub fn get_treasurer(&self) -> AccountId {
+        self.treasurer.clone()
+    }
+}
+
+impl Contract {
+    pub(crate) fn internal_fee_config(&self, ft_account_id: &AccountId) -> FeeConfig {
+        self.fee_configs.get(ft_account_id).unwrap_or_default()
+    }
+
+    pub(crate) fn internal_collect_fees(&mut self, fees: &Fees) {
+        if fees.total() == 0 {
+            return;
+        }
+        let mut treasury = self.treasury.get(&fees.ft_account_id).unwrap_or_else(Treasury::new);
+        treasury.balance = add(treasury.balance, U128::from(fees.total()));
+        treasury.performance = add(treasury.performance, fees.performance);
+        treasury.deposit = add(treasury.deposit, fees.deposit);
+        treasury.withdrawal = add(treasury.withdrawal, fees.withdrawal);
+        treasury.penalty = add(treasury.penalty, fees.penalty);
+        self.treasury.insert(&fees.ft_account_id, &treasury);
+    }
+
+    //deposit fee of a new stake or top up, collected right away, returns the amount left to stake
+    pub(crate) fn internal_take_deposit_fee(&mut self, ft_account_id: &AccountId, amount: u128) -> u128 {
+        let mut fees = Fees::new(ft_account_id);
+        fees.on_deposit(amount, &self.internal_fee_config(ft_account_id));
+        self.internal_collect_fees(&fees);
+        amount - fees.total()
+    }
+
+    pub(crate) fn assert_treasurer(&self) {
+        assert_eq!(
+            env::predecessor_account_id(),
+            self.treasurer,
+            "Treasurer's method"
+        );
+    }
+}
@@ -1,21 +1,18 @@
 use core::panic;
 
 use crate::*;
+use crate::events::{emit_nft_burn, emit_nft_mint};
 
-use near_sdk::collections::UnorderedMap;
-use near_sdk::env::{attached_deposit, log};
+use near_sdk::env::attached_deposit;
 use near_sdk::json_types::U128;
 use near_sdk::{ext_contract, log, Balance, Gas, PromiseOrValue, PromiseResult};
 
-const BASE_GAS: Gas = Gas(5_000_000_000_000);
-const STORAGE_DEPOSIT_GAS: Gas = Gas(300_000_000_000_000);
+pub(crate) const BASE_GAS: Gas = Gas(5_000_000_000_000);
 
 //const THIRTY_DAYS: u64 = 2592000; //30 days in seconds
-const ONE_MINUTE: u64 = 60; //30 days in seconds
 
 const STORAGE_DEPOSIT: Balance = 8590000000000000000000;
 
-const ONE_YOCTO: Balance = 100000000000000000000000;
 const TEN_THOUSAND: u128 = 10000000000000000000000000000;
 const NO_BALANCE: Balance = 0;
 
@@ -27,21 +24,66 @@ pub trait FTActionsReceiver {
         msg: String,
     ) -> PromiseOrValue<U128>;
 
-    fn ft_unstake(&mut self, stake_id: U128);
+    fn ft_unstake(&mut self, stake_id: U128, amount: Option<U128>);
 
     fn did_promise_succeded() -> bool;
 
-    fn unstake_callback(&mut self, stake_id: StakeId, staker_id: AccountId);
+    fn unstake_callback(&mut self, stake_id: StakeId, staker_id: AccountId, unstaked: UnstakedAmount, fees: Fees);
+
+    fn unstake_matured(&mut self, token_id: AccountId);
+
+    fn request_unstake(&mut self, stake_id: StakeId);
+
+    fn withdraw_unbonded(&mut self);
+
+    fn withdraw_unbonded_callback(
+        &mut self,
+        staker_id: AccountId,
+        entries: Vec<Unbonding>,
+        fees: Fees,
+    );
+
+    fn unstake_matured_callback(&mut self, staker_id: AccountId, stakes: Vec<Stake>, fees: Fees);
 
     fn claim_reward(&mut self, stake_id: StakeId);
 
-    fn claim_reward_callback(
+    fn flexible_withdraw(&mut self, ft_account_id: AccountId, staking_plan: String, amount: U128);
+
+    fn flexible_claim(&mut self, ft_account_id: AccountId, staking_plan: String);
+
+    fn flexible_callback(&mut self, staker_id: AccountId, withdrawal: PoolWithdrawal, fees: Fees);
+
+    fn claim_all(&mut self, token_id: Option<AccountId>);
+
+    fn claim_all_callback(
         &mut self,
-        stake_id: StakeId,
-        claim_history: Option<ClaimHistory>,
-        claim_count: u64,
+        staker_id: AccountId,
+        claimed_stakes: Vec<ClaimedStake>,
+        fees: Fees,
     );
 
+    fn split_stake(&mut self, stake_id: StakeId, amounts: Vec<U128>) -> Vec<StakeId>;
+
+    fn claim_reward_callback(&mut self, staker_id: AccountId, claimed: ClaimedStake, fees: Fees);
+
+    fn restake(&mut self, stake_id: StakeId, staking_plan: String, duration: u64, with_reward: bool);
+
+    fn set_auto_renew(&mut self, stake_id: StakeId, auto_renew: bool);
+
+    fn extend_lock(&mut self, stake_id: StakeId, new_plan: String, duration: Option<u64>);
+
+    fn set_beneficiary(&mut self, stake_id: StakeId, beneficiary: Option<AccountId>);
+
+    fn set_operator(&mut self, stake_id: StakeId, operator: Option<AccountId>);
+
+    fn transfer_stake(&mut self, stake_id: StakeId, receiver_id: AccountId);
+
+    fn claim_referral_rewards(&mut self, ft_account_id: AccountId);
+
+    fn referral_callback(&mut self, referrer_id: AccountId, ft_account_id: AccountId, amount: U128);
+
+    fn treasury_callback(&mut self, token: AccountId, amount: U128);
+
     fn deposit_for_storage(&mut self, ft_contract_id: AccountId);
 
     fn drop_ft(&mut self, account_id: AccountId, ft_contract_id: AccountId);
@@ -57,16 +99,33 @@ trait FTCallbackReceiver {
 
 #[ext_contract(this_contract)]
 trait FTActionsSender {
-    fn unstake_callback(&mut self, stake_id: StakeId, staker_id: AccountId);
+    fn unstake_callback(&mut self, stake_id: StakeId, staker_id: AccountId, unstaked: UnstakedAmount, fees: Fees);
+
+    fn claim_reward_callback(&mut self, staker_id: AccountId, claimed: ClaimedStake, fees: Fees);
+
+    fn claim_all_callback(
+        &mut self,
+        staker_id: AccountId,
+        claimed_stakes: Vec<ClaimedStake>,
+        fees: Fees,
+    );
+
+    fn flexible_callback(&mut self, staker_id: AccountId, withdrawal: PoolWithdrawal, fees: Fees);
+
+    fn unstake_matured_callback(&mut self, staker_id: AccountId, stakes: Vec<Stake>, fees: Fees);
 
-    fn claim_reward_callback(
+    fn withdraw_unbonded_callback(
         &mut self,
-        stake_id: StakeId,
-        claim_history: Option<ClaimHistory>,
-        claim_count: u64,
+        staker_id: AccountId,
+        entries: Vec<Unbonding>,
+        fees: Fees,
     );
 
     fn drop_ft_callback(&mut self, account_id: AccountId, ft_contract_id: AccountId);
+
+    fn referral_callback(&mut self, referrer_id: AccountId, ft_account_id: AccountId, amount: U128);
+
+    fn treasury_callback(&mut self, token: AccountId, amount: U128);
 }
 
 #[near_bindgen]
@@ -77,11 +136,39 @@ impl FTActionsReceiver for Contract {
         amount: U128,
         msg: String,
     ) -> PromiseOrValue<U128> {
-        let a: u128 = 0;
-        let staking_id = self
-            .staking_nonce
-            .checked_add(1)
-            .expect("Exceeded u128 capacity");
+        //deposits over the staking caps are returned to the sender as unused
+        if let Ok(action) = near_sdk::serde_json::from_str::<StakeAction>(&msg) {
+            let unused = match action {
+                StakeAction::TopUp { stake_id } => self.internal_top_up(&sender_id, stake_id, amount),
+            };
+            return near_sdk::PromiseOrValue::Value(U128::from(unused));
+        }
+
+        let args: StakeArgs = near_sdk::serde_json::from_str(&msg).expect("Invalid Staking Arguments");
+        //fees and stakes are booked on the FT named in the message, it has to be the one that sent the tokens
+        assert_eq!(
+            args.ft_account_id,
+            env::predecessor_account_id(),
+            "Cannot stake tokens of another FT"
+        );
+        let is_flexible = self
+            .approved_fts
+            .get(&args.ft_account_id)
+            .and_then(|ft| ft.apy_against_duration)
+            .and_then(|apy_map| apy_map.get(&args.staking_plan).cloned())
+            .is_some_and(|apy| apy.plan_type == PlanType::Flexible);
+        if is_flexible {
+            let unused = self.internal_flexible_deposit(
+                &sender_id,
+                &args.ft_account_id,
+                &args.staking_plan,
+                amount,
+                args.referrer.clone(),
+            );
+            return near_sdk::PromiseOrValue::Value(U128::from(unused));
+        }
+
+        let staking_id = u128::from(self.internal_next_stake_id());
 
         log!("{} staking id", staking_id);
         let staked_at = env::block_timestamp() / 1000000000;
@@ -92,18 +179,37 @@ impl FTActionsReceiver for Contract {
             duration,
             staked_by,
             staking_plan,
-        } = near_sdk::serde_json::from_str(&msg).expect("Invalid Staking Arguments");
+            auto_renew,
+            beneficiary,
+            operator,
+            referrer,
+        } = args;
+        let referrer = self.internal_set_referrer(&sender_id, &ft_account_id, referrer);
+        let accepted =
+            self.internal_accept_deposit(&sender_id, &ft_account_id, &staking_plan, u128::from(amount));
+        let unused = u128::from(amount) - accepted;
+        //the deposit fee goes to the treasury and the rest is staked
+        let amount = U128::from(self.internal_take_deposit_fee(&ft_account_id, accepted));
 
-        let stake = Stake {
+        let mut stake = Stake {
             stake_id: U128::from(staking_id),
             ft_symbol,
             ft_account_id,
             decimal,
             amount,
             duration,
-            staked_at: staked_at,
+            staked_at,
             staked_by,
             staking_plan,
+            compounded_at: staked_at,
+            unclaimed_reward: U128::from(0),
+            auto_renew,
+            beneficiary,
+            operator,
+            receipt_amount: U128::from(0),
+            reward_debt: U128::from(0),
+            weight: U128::from(0),
+            referrer,
         };
 
         //fetch apy details from FT
@@ -123,66 +229,126 @@ impl FTActionsReceiver for Contract {
             "Cannot stake less than {} tokens",
             threshold
         );
+        Contract::assert_plan_open(apy.unwrap(), staked_at);
+        stake.duration = Contract::plan_duration(apy.unwrap(), staked_at, stake.duration);
         assert!(
-            stake.duration / ONE_MINUTE >= apy.unwrap().min_duration.into(),
+            apy.unwrap().matures_at.is_some()
+                || stake.duration / ONE_MINUTE >= apy.unwrap().min_duration.into(),
             "Invalid Duration"
         );
+        assert!(
+            !stake.auto_renew || apy.unwrap().matures_at.is_none(),
+            "Stakes of a plan with a maturity date cannot auto renew"
+        );
+        self.assert_can_stake(&sender_id, &stake.ft_account_id, apy.unwrap());
+
+        let receipt_amount =
+            self.internal_mint_receipt(&calling_contrat, &sender_id, u128::from(amount));
+        stake.receipt_amount = U128::from(receipt_amount);
+        self.internal_join_pool(&mut stake);
+        self.internal_track_stake(&sender_id, &stake.ft_account_id, &stake.staking_plan, true);
 
         if let Some(mut staking_history) = self.staking_history.get(&sender_id) {
             log!("In IF");
             staking_history.push(stake);
-            self.staking_history.insert(&sender_id, &staking_history);
+            self.internal_save_stakes(&sender_id, &staking_history);
         } else {
             log!("In ELSE");
-            let mut staking_history: Vec<Stake> = Vec::new();
-            staking_history.push(stake);
-            self.staking_history.insert(&sender_id, &staking_history);
+            let staking_history: Vec<Stake> = vec![stake];
+            self.internal_save_stakes(&sender_id, &staking_history);
         }
 
+        self.stake_owners.insert(&U128::from(staking_id), &sender_id);
+        emit_nft_mint(&sender_id, vec![staking_id.to_string()]);
+
         log!(
             "{:?} staked by {} with staking_id {}",
             amount,
             sender_id,
             staking_id
         );
-        self.staking_nonce = staking_id;
-        near_sdk::PromiseOrValue::Value(U128::from(a))
+        near_sdk::PromiseOrValue::Value(U128::from(unused))
     }
 
-    fn ft_unstake(&mut self, stake_id: StakeId) {
+    fn ft_unstake(&mut self, stake_id: StakeId, amount: Option<U128>) {
         // let stake_id = u128::from(stake_id);
-        let staker_id: AccountId = env::predecessor_account_id().try_into().unwrap();
-        let stake_history = self.staking_history.get(&staker_id);
+        //the operator of a stake can unstake on behalf of the staker
+        let (staker_id, mut stake) =
+            self.internal_get_managed_stake(&env::predecessor_account_id(), stake_id);
 
-        // assert!(self.whitelist_addresses.contains(&staker_id), "Only whitelisted members can unstake tokens");
-
-        let stake = stake_history
-            .unwrap()
-            .into_iter()
-            .find(|i| i.stake_id == stake_id)
-            .expect("No staking data with this id found for caller");
+        //denied accounts can still unstake, but only get their principal back
+        let denied = self.denylist.contains(&staker_id);
 
         let current_time = env::block_timestamp() / 1000000000;
-        let staked_at = stake.staked_at;
-        let duration = stake.duration;
-        let amount = stake.amount;
-        let staked_by = stake.staked_by;
-        let ft_contract: AccountId = stake.ft_account_id.try_into().unwrap();
+        let unlocks_at = Contract::unlocks_at(&stake, current_time);
+        let owner_id = self.internal_stake_owner(&stake);
+        let ft_contract: AccountId = stake.ft_account_id.clone();
+        let receiver_id = Contract::payout_receiver(&stake);
         let memo: Option<String> = Some("Unstaking with reward".to_string());
 
         assert_eq!(
-            staked_by.to_string(),
+            owner_id.to_string(),
             staker_id.to_string(),
             "Only owner of the tokens can unstake"
         );
 
+        //stakes can only leave before the lock ends when the FT has an early unstake penalty
+        let fee_config = self.internal_fee_config(&stake.ft_account_id);
+        let early = current_time < unlocks_at;
         assert!(
-            current_time >= staked_at + duration,
+            !early || fee_config.early_unstake_penalty_bps > 0,
             "Cannot withdraw before locked time"
         );
+        let apy = self.internal_get_apy(&stake.ft_account_id, &stake.staking_plan);
+        assert!(
+            apy.unbonding_period.is_none(),
+            "Stakes of this plan are withdrawn with request_unstake"
+        );
+
+        //every reward accrued so far is settled, a full unstake pays the unclaimed ones with the amount
+        //and auto-compounding stakes already hold theirs in the amount
+        self.internal_leave_pool(&mut stake);
+
+        let withdraw_amount: u128;
+        let receipt_amount: u128;
+        let mut fees = Fees::new(&ft_contract);
+        if let Some(partial_amount) = amount {
+            //a partial withdrawal is taken out now and given back if the transfer fails
+            withdraw_amount = u128::from(partial_amount);
+            let staked_amount = u128::from(stake.amount);
+            assert!(withdraw_amount > 0, "Cannot unstake 0 tokens");
+            assert!(
+                withdraw_amount < staked_amount,
+                "Cannot unstake more than {} tokens, unstake the whole stake instead",
+                staked_amount
+            );
+            let threshold: u128 = u128::from(apy.min_staking_amount);
+            assert!(
+                staked_amount - withdraw_amount >= threshold,
+                "Cannot leave less than {} tokens staked",
+                threshold
+            );
+            fees.on_withdrawal(withdraw_amount, &fee_config, early);
+            receipt_amount = min(withdraw_amount, u128::from(stake.receipt_amount));
+            stake.amount = U128::from(staked_amount - withdraw_amount);
+            stake.receipt_amount = U128::from(u128::from(stake.receipt_amount) - receipt_amount);
+            self.internal_join_pool(&mut stake);
+            self.internal_update_stake(&staker_id, &stake);
+        } else {
+            //settled rewards that were not claimed yet are paid out with the amount
+            let reward = if denied { 0 } else { u128::from(stake.unclaimed_reward) };
+            withdraw_amount = u128::from(stake.amount) + reward;
+            fees.on_withdrawal(u128::from(stake.amount), &fee_config, early);
+            fees.on_rewards(reward, &fee_config);
+            receipt_amount = u128::from(stake.receipt_amount);
+            self.internal_update_stake(&staker_id, &stake);
+        }
+        //receipts are burned now and minted again if the transfer fails
+        self.internal_burn_receipt(&staker_id, receipt_amount);
+
         ext_ft::ft_transfer(
-            staker_id.clone(),
-            U128::from(amount),
+            receiver_id,
+            U128::from(withdraw_amount - fees.total()),
             memo,
             ft_contract,
             1,
@@ -191,6 +357,11 @@ impl FTActionsReceiver for Contract {
         .then(this_contract::unstake_callback(
             stake_id,
             staker_id,
+            UnstakedAmount {
+                amount,
+                receipt_amount: U128::from(receipt_amount),
+            },
+            fees,
             env::current_account_id(),
             0,
             BASE_GAS,
@@ -204,20 +375,46 @@ impl FTActionsReceiver for Contract {
             log!("Expected a result on the callback");
             return false;
         }
-        match env::promise_result(0) {
-            PromiseResult::Successful(_) => true,
-            _ => false,
-        }
+        matches!(env::promise_result(0), PromiseResult::Successful(_))
     }
 
-    fn unstake_callback(&mut self, stake_id: StakeId, staker_id: AccountId) {
-        if Self::did_promise_succeded() {
+    #[private]
+    fn unstake_callback(&mut self, stake_id: StakeId, staker_id: AccountId, unstaked: UnstakedAmount, fees: Fees) {
+        let UnstakedAmount { amount, receipt_amount } = unstaked;
+        if !Self::did_promise_succeded() {
+            let mut stake = self.internal_get_stake(&staker_id, stake_id);
+            let minted =
+                self.internal_mint_receipt(&stake.ft_account_id, &staker_id, u128::from(receipt_amount));
+            if let Some(partial_amount) = amount {
+                self.internal_leave_pool(&mut stake);
+                stake.amount = U128::from(u128::from(stake.amount) + u128::from(partial_amount));
+                stake.receipt_amount = U128::from(u128::from(stake.receipt_amount) + minted);
+                self.internal_join_pool(&mut stake);
+                self.internal_update_stake(&staker_id, &stake);
+                log!(
+                    "Restored {} tokens to Staking ID {}",
+                    u128::from(partial_amount),
+                    u128::from(stake_id)
+                );
+            } else {
+                self.internal_join_pool(&mut stake);
+                self.internal_update_stake(&staker_id, &stake);
+            }
+            return;
+        }
+        self.internal_collect_fees(&fees);
+        //a partial withdrawal was already taken out of the stake
+        if amount.is_none() {
             let mut staking_history = self.staking_history.get(&staker_id).unwrap();
             let index = &staking_history.iter().position(|i| i.stake_id == stake_id);
 
-            let _ = &staking_history.remove(index.unwrap());
+            let stake = staking_history.remove(index.unwrap());
+            self.internal_withdrawn_rewards(&staker_id, &stake);
+            self.internal_track_stake(&staker_id, &stake.ft_account_id, &stake.staking_plan, false);
 
-            self.staking_history.insert(&staker_id, &staking_history);
+            self.internal_save_stakes(&staker_id, &staking_history);
+            self.stake_owners.remove(&stake_id);
+            emit_nft_burn(&staker_id, vec![u128::from(stake_id).to_string()]);
 
             log!(
                 "Staking ID {} removed from {}",
@@ -227,35 +424,363 @@ impl FTActionsReceiver for Contract {
         }
     }
 
-    fn claim_reward(&mut self, stake_id: StakeId) {
-        let staker_id: AccountId = env::predecessor_account_id().try_into().unwrap();
+    fn unstake_matured(&mut self, token_id: AccountId) {
+        let staker_id: AccountId = env::predecessor_account_id();
         let stake_history = self
             .staking_history
             .get(&staker_id)
             .expect("This user has not staked yet.");
+        let current_time = env::block_timestamp() / 1000000000;
+
+        //matured stakes are taken out now and given back if the transfer fails
+        let mut remaining: Vec<Stake> = Vec::new();
+        let mut matured: HashMap<AccountId, (u128, Vec<Stake>, Fees)> = HashMap::new();
+        let fee_config = self.internal_fee_config(&token_id);
+        let denied = self.denylist.contains(&staker_id);
+        for mut stake in stake_history {
+            if stake.ft_account_id != token_id
+                || stake.staked_by != staker_id
+                || current_time < Contract::unlocks_at(&stake, current_time)
+                || self
+                    .internal_get_apy(&stake.ft_account_id, &stake.staking_plan)
+                    .unbonding_period
+                    .is_some()
+            {
+                remaining.push(stake);
+                continue;
+            }
+            self.internal_leave_pool(&mut stake);
+            let entry = matured
+                .entry(Contract::payout_receiver(&stake))
+                .or_insert((0, Vec::new(), Fees::new(&token_id)));
+            let reward = if denied { 0 } else { u128::from(stake.unclaimed_reward) };
+            entry.0 += u128::from(stake.amount) + reward;
+            entry.2.on_withdrawal(u128::from(stake.amount), &fee_config, false);
+            entry.2.on_rewards(reward, &fee_config);
+            entry.1.push(stake);
+        }
+        assert!(!matured.is_empty(), "No matured stakes to withdraw");
+        self.internal_save_stakes(&staker_id, &remaining);
+        let receipt_amount: u128 = matured
+            .values()
+            .flat_map(|(_, stakes, _)| stakes.iter())
+            .map(|stake| u128::from(stake.receipt_amount))
+            .sum();
+        self.internal_burn_receipt(&staker_id, receipt_amount);
+
+        //one transfer per beneficiary
+        for (receiver_id, (amount, stakes, fees)) in matured {
+            ext_ft::ft_transfer(
+                receiver_id,
+                U128::from(amount - fees.total()),
+                Some("Unstaking with reward".to_string()),
+                token_id.clone(),
+                1,
+                BASE_GAS,
+            )
+            .then(this_contract::unstake_matured_callback(
+                staker_id.clone(),
+                stakes,
+                fees,
+                env::current_account_id(),
+                0,
+                BASE_GAS,
+            ));
+        }
+    }
 
-        let stake = stake_history
+    #[private]
+    fn unstake_matured_callback(&mut self, staker_id: AccountId, stakes: Vec<Stake>, fees: Fees) {
+        let stake_ids: Vec<u128> = stakes.iter().map(|i| u128::from(i.stake_id)).collect();
+        if Self::did_promise_succeded() {
+            self.internal_collect_fees(&fees);
+            for stake in stakes.iter() {
+                self.stake_owners.remove(&stake.stake_id);
+                self.internal_withdrawn_rewards(&staker_id, stake);
+                self.internal_track_stake(&staker_id, &stake.ft_account_id, &stake.staking_plan, false);
+            }
+            emit_nft_burn(&staker_id, stake_ids.iter().map(|id| id.to_string()).collect());
+            log!("Staking IDs {:?} removed", stake_ids);
+        } else {
+            let receipt_amount: u128 = stakes.iter().map(|i| u128::from(i.receipt_amount)).sum();
+            if let Some(stake) = stakes.first() {
+                self.internal_mint_receipt(&stake.ft_account_id, &staker_id, receipt_amount);
+            }
+            let mut staking_history = self.staking_history.get(&staker_id).unwrap_or_default();
+            for mut stake in stakes {
+                self.internal_join_pool(&mut stake);
+                staking_history.push(stake);
+            }
+            self.internal_save_stakes(&staker_id, &staking_history);
+            log!("Staking IDs {:?} restored", stake_ids);
+        }
+    }
+
+    fn request_unstake(&mut self, stake_id: StakeId) {
+        let staker_id: AccountId = env::predecessor_account_id();
+        let mut stake = self.internal_get_stake(&staker_id, stake_id);
+        assert_eq!(
+            self.internal_stake_owner(&stake).to_string(),
+            staker_id.to_string(),
+            "Only owner of the tokens can unstake"
+        );
+        let apy = self.internal_get_apy(&stake.ft_account_id, &stake.staking_plan);
+        let unbonding_period = apy
+            .unbonding_period
+            .expect("This staking plan has no unbonding period");
+
+        //the stake stops earning rewards once it is unbonding
+        self.internal_leave_pool(&mut stake);
+        self.internal_track_stake(&staker_id, &stake.ft_account_id, &stake.staking_plan, false);
+        let mut staking_history = self.staking_history.get(&staker_id).unwrap();
+        staking_history.retain(|i| i.stake_id != stake_id);
+        self.internal_save_stakes(&staker_id, &staking_history);
+        self.stake_owners.remove(&stake_id);
+        emit_nft_burn(&staker_id, vec![u128::from(stake_id).to_string()]);
+        self.internal_burn_receipt(&staker_id, u128::from(stake.receipt_amount));
+        stake.receipt_amount = U128::from(0);
+
+        let current_time = env::block_timestamp() / 1000000000;
+        let mut unbonding = self.unbonding.get(&staker_id).unwrap_or_default();
+        unbonding.push(Unbonding {
+            stake,
+            requested_at: current_time,
+            unbonds_at: current_time + unbonding_period,
+        });
+        self.unbonding.insert(&staker_id, &unbonding);
+
+        log!(
+            "Staking ID {} unbonding until {}",
+            u128::from(stake_id),
+            current_time + unbonding_period
+        );
+    }
+
+    fn withdraw_unbonded(&mut self) {
+        let staker_id: AccountId = env::predecessor_account_id();
+        let current_time = env::block_timestamp() / 1000000000;
+        let (unbonded, unbonding): (Vec<Unbonding>, Vec<Unbonding>) = self
+            .unbonding
+            .get(&staker_id)
+            .unwrap_or_default()
             .into_iter()
-            .find(|i| i.stake_id == stake_id)
-            .expect("No staking data with this id found for caller");
+            .partition(|entry| current_time >= entry.unbonds_at);
+        assert!(!unbonded.is_empty(), "No unbonded stakes to withdraw");
+        self.unbonding.insert(&staker_id, &unbonding);
+
+        //one transfer per token and beneficiary, given back to the queue if it fails
+        let mut transfers: HashMap<(AccountId, AccountId), (u128, Vec<Unbonding>, Fees)> =
+            HashMap::new();
+        let denied = self.denylist.contains(&staker_id);
+        for entry in unbonded {
+            let reward = if denied { 0 } else { u128::from(entry.stake.unclaimed_reward) };
+            let entry_amount = u128::from(entry.stake.amount) + reward;
+            let fee_config = self.internal_fee_config(&entry.stake.ft_account_id);
+            let transfer = transfers
+                .entry((
+                    entry.stake.ft_account_id.clone(),
+                    Contract::payout_receiver(&entry.stake),
+                ))
+                .or_insert((0, Vec::new(), Fees::new(&entry.stake.ft_account_id)));
+            transfer.0 += entry_amount;
+            transfer.2.on_withdrawal(u128::from(entry.stake.amount), &fee_config, false);
+            transfer.2.on_rewards(reward, &fee_config);
+            transfer.1.push(entry);
+        }
+        for ((ft_contract, receiver_id), (amount, entries, fees)) in transfers {
+            ext_ft::ft_transfer(
+                receiver_id,
+                U128::from(amount - fees.total()),
+                Some("Unstaking with reward".to_string()),
+                ft_contract,
+                1,
+                BASE_GAS,
+            )
+            .then(this_contract::withdraw_unbonded_callback(
+                staker_id.clone(),
+                entries,
+                fees,
+                env::current_account_id(),
+                0,
+                BASE_GAS,
+            ));
+        }
+    }
+
+    #[private]
+    fn withdraw_unbonded_callback(
+        &mut self,
+        staker_id: AccountId,
+        entries: Vec<Unbonding>,
+        fees: Fees,
+    ) {
+        let stake_ids: Vec<u128> = entries.iter().map(|i| u128::from(i.stake.stake_id)).collect();
+        if Self::did_promise_succeded() {
+            self.internal_collect_fees(&fees);
+            for entry in entries.iter() {
+                self.internal_withdrawn_rewards(&staker_id, &entry.stake);
+            }
+            log!("Unbonded Staking IDs {:?} withdrawn", stake_ids);
+        } else {
+            let mut unbonding = self.unbonding.get(&staker_id).unwrap_or_default();
+            unbonding.extend(entries);
+            self.unbonding.insert(&staker_id, &unbonding);
+            log!("Unbonded Staking IDs {:?} restored", stake_ids);
+        }
+    }
+
+    fn flexible_withdraw(&mut self, ft_account_id: AccountId, staking_plan: String, amount: U128) {
+        let staker_id: AccountId = env::predecessor_account_id();
+        let (mut pool, _) = self.internal_get_pool(&ft_account_id, &staking_plan);
+        let mut position = self.internal_get_position(&staker_id, &ft_account_id, &staking_plan);
+        let staked_amount = u128::from(position.amount);
+        let withdraw_amount = u128::from(amount);
+        assert!(withdraw_amount > 0, "Cannot unstake 0 tokens");
+        assert!(
+            withdraw_amount <= staked_amount,
+            "Cannot unstake more than {} tokens",
+            staked_amount
+        );
+
+        let mut fees = Fees::new(&ft_account_id);
+        fees.on_withdrawal(withdraw_amount, &self.internal_fee_config(&ft_account_id), false);
+
+        //taken out now and given back if the transfer fails
+        position.settle(&pool);
+        position.set_amount(staked_amount - withdraw_amount, &pool);
+        pool.total_staked = U128::from(u128::from(pool.total_staked) - withdraw_amount);
+        self.internal_save_position(&staker_id, &ft_account_id, &staking_plan, &pool, &position);
+        self.internal_remove_staked(&ft_account_id, &staking_plan, withdraw_amount);
+        if withdraw_amount == staked_amount {
+            self.internal_track_stake(&staker_id, &ft_account_id, &staking_plan, false);
+        }
+
+        ext_ft::ft_transfer(
+            staker_id.clone(),
+            U128::from(withdraw_amount - fees.total()),
+            Some("Unstaking".to_string()),
+            ft_account_id.clone(),
+            1,
+            BASE_GAS,
+        )
+        .then(this_contract::flexible_callback(
+            staker_id,
+            PoolWithdrawal {
+                ft_account_id,
+                staking_plan,
+                amount,
+                reward: U128::from(0),
+            },
+            fees,
+            env::current_account_id(),
+            0,
+            BASE_GAS,
+        ));
+    }
+
+    fn flexible_claim(&mut self, ft_account_id: AccountId, staking_plan: String) {
+        let staker_id: AccountId = env::predecessor_account_id();
+        self.assert_not_denied(&staker_id);
+        let (pool, _) = self.internal_get_pool(&ft_account_id, &staking_plan);
+        let mut position = self.internal_get_position(&staker_id, &ft_account_id, &staking_plan);
+        position.settle(&pool);
+        let reward = position.pending_reward;
+        assert!(u128::from(reward) > 0, "No rewards to claim");
+        position.pending_reward = U128::from(0);
+        self.internal_save_position(&staker_id, &ft_account_id, &staking_plan, &pool, &position);
+        let mut fees = Fees::new(&ft_account_id);
+        fees.on_rewards(u128::from(reward), &self.internal_fee_config(&ft_account_id));
+
+        ext_ft::ft_transfer(
+            staker_id.clone(),
+            U128::from(u128::from(reward) - fees.total()),
+            Some("Reward tokens".to_string()),
+            ft_account_id.clone(),
+            1,
+            BASE_GAS,
+        )
+        .then(this_contract::flexible_callback(
+            staker_id,
+            PoolWithdrawal {
+                ft_account_id,
+                staking_plan,
+                amount: U128::from(0),
+                reward,
+            },
+            fees,
+            env::current_account_id(),
+            0,
+            BASE_GAS,
+        ));
+    }
+
+    #[private]
+    fn flexible_callback(&mut self, staker_id: AccountId, withdrawal: PoolWithdrawal, fees: Fees) {
+        let PoolWithdrawal {
+            ft_account_id,
+            staking_plan,
+            amount,
+            reward,
+        } = withdrawal;
+        if Self::did_promise_succeded() {
+            self.internal_collect_fees(&fees);
+            self.internal_release_rewards(&ft_account_id, &staking_plan, u128::from(reward), true);
+            self.internal_add_claimed(&staker_id, &ft_account_id, u128::from(reward));
+            let referrer = self.referrers.get(&staker_id);
+            self.internal_accrue_referral(referrer.as_ref(), &ft_account_id, u128::from(reward));
+            return;
+        }
+        let (mut pool, _) = self.internal_get_pool(&ft_account_id, &staking_plan);
+        let mut position = self.internal_get_position(&staker_id, &ft_account_id, &staking_plan);
+        if u128::from(position.amount) == 0 && u128::from(amount) > 0 {
+            self.internal_track_stake(&staker_id, &ft_account_id, &staking_plan, true);
+        }
+        position.settle(&pool);
+        let restored = u128::from(position.amount) + u128::from(amount);
+        position.set_amount(restored, &pool);
+        position.pending_reward = U128::from(u128::from(position.pending_reward) + u128::from(reward));
+        pool.total_staked = U128::from(u128::from(pool.total_staked) + u128::from(amount));
+        self.internal_save_position(&staker_id, &ft_account_id, &staking_plan, &pool, &position);
+        self.internal_add_staked(&ft_account_id, &staking_plan, u128::from(amount));
+        log!(
+            "Restored {} tokens and {} reward to {}",
+            u128::from(amount),
+            u128::from(reward),
+            staker_id
+        );
+    }
+
+    fn claim_reward(&mut self, stake_id: StakeId) {
+        //the operator of a stake can claim on behalf of the staker
+        let (staker_id, mut stake) =
+            self.internal_get_managed_stake(&env::predecessor_account_id(), stake_id);
+        let receiver_id = Contract::payout_receiver(&stake);
+
+        //get FT details
+        let apy = self.internal_get_apy(&stake.ft_account_id, &stake.staking_plan);
+        //emission rewards come from the pool, they are settled before being paid like unclaimed rewards
+        if apy.plan_type == PlanType::Emission {
+            self.internal_settle_reward(&mut stake);
+            self.internal_update_stake(&staker_id, &stake);
+        }
 
         let current_time = env::block_timestamp() / 1000000000;
         //let current_time = 1653764399;
         let staked_at = stake.staked_at;
         // let duration = stake.duration / THIRTY_DAYS;
-        let amount = u128::from(stake.amount);
-        let staked_by = stake.staked_by;
+        let unclaimed_reward = u128::from(stake.unclaimed_reward);
+        let owner_id = self.internal_stake_owner(&stake);
         // let symbol = stake.ft_symbol;
         // let decimal = stake.decimal;
 
         let claim_history = self.claim_history.get(&stake_id);
 
         assert_eq!(
-            staked_by.to_string(),
+            owner_id.to_string(),
             staker_id.to_string(),
             "Only owner of the tokens can claim reward"
         );
-        //  assert!(self.whitelist_addresses.contains(&staker_id), "Only whitelisted members can claim reward tokens");
+        self.assert_not_denied(&staker_id);
 
         let difference: u64;
         if claim_history.is_none() {
@@ -263,7 +788,7 @@ impl FTActionsReceiver for Contract {
             //difference = 1;
             log!("{}", difference);
             assert!(
-                difference >= 1,
+                difference >= 1 || unclaimed_reward > 0,
                 "Reward can be claimed after staking for 1 minute"
             );
         } else {
@@ -277,68 +802,478 @@ impl FTActionsReceiver for Contract {
                 difference
             );
             assert!(
-                difference >= 1,
+                difference >= 1 || unclaimed_reward > 0,
                 "Reward can be claimed after 1 minute of the last claim"
             );
         }
 
-        //get FT details
-        let ft = self.approved_fts.get(&stake.ft_account_id).unwrap();
-        let apy_map = ft.apy_against_duration.unwrap();
-        let apy = apy_map.get(&stake.staking_plan).unwrap();
-
-        //log!("{:?}", apy);
-        //let ap = apy.interest_rate / apy.min_duration as u16;
-        let ap = apy.interest_rate;
+        assert!(
+            !apy.auto_compound,
+            "Rewards of auto-compounding stakes are added to the staked amount"
+        );
 
-        let interest = (amount * (ap as u128)) / 100;
-        log!("interest {}", interest);
-        let mut actual_amount = (interest / 10) * difference as u128; //calculae the reward according to the number of months passed since the user staked
-        log!("actual_amount {}", actual_amount);
-        actual_amount = actual_amount / apy.min_duration as u128;
+        //calculae the reward according to the number of minutes passed since the last claim
+        let actual_amount = self.internal_pending_reward(&stake);
         log!("Actual amount for transfer {}", actual_amount);
+        let mut fees = Fees::new(&stake.ft_account_id);
+        fees.on_rewards(actual_amount, &self.internal_fee_config(&stake.ft_account_id));
+
+        let claimed = ClaimedStake {
+            stake_id,
+            unclaimed_reward: U128::from(unclaimed_reward),
+            reward: U128::from(actual_amount),
+            claim_history,
+            claim_count: difference,
+        };
+        //recorded before the transfer and restored by the callback if it fails
+        self.internal_record_claim(&staker_id, &claimed);
 
         let memo: Option<String> = Some("Reward tokens".to_string());
         ext_ft::ft_transfer(
-            staker_id.clone(),
-            U128::from(actual_amount),
+            receiver_id,
+            U128::from(actual_amount - fees.total()),
             memo,
             stake.ft_account_id,
             1,
             BASE_GAS,
         )
         .then(this_contract::claim_reward_callback(
-            stake_id,
-            claim_history.clone(),
-            difference,
+            staker_id,
+            claimed,
+            fees,
             env::current_account_id(),
             0,
             BASE_GAS,
         ));
     }
 
-    fn claim_reward_callback(
+    #[private]
+    fn claim_reward_callback(&mut self, staker_id: AccountId, claimed: ClaimedStake, fees: Fees) {
+        if Self::did_promise_succeded() {
+            self.internal_collect_fees(&fees);
+            self.internal_claim_paid(&staker_id, &claimed);
+        } else {
+            self.internal_restore_claim(&staker_id, &claimed);
+        }
+    }
+
+    fn claim_all(&mut self, token_id: Option<AccountId>) {
+        let staker_id: AccountId = env::predecessor_account_id();
+        self.assert_not_denied(&staker_id);
+        let stake_history = self
+            .staking_history
+            .get(&staker_id)
+            .expect("This user has not staked yet.");
+        let current_time = env::block_timestamp() / 1000000000;
+
+        //one transfer per reward token covering every claimable stake of that token
+        let mut rewards: HashMap<(AccountId, AccountId), (u128, Vec<ClaimedStake>, Fees)> =
+            HashMap::new();
+        for mut stake in stake_history {
+            if token_id.as_ref().is_some_and(|token| token != &stake.ft_account_id)
+                || stake.staked_by != staker_id
+            {
+                continue;
+            }
+            let apy = self.internal_get_apy(&stake.ft_account_id, &stake.staking_plan);
+            if apy.auto_compound {
+                continue;
+            }
+            if apy.plan_type == PlanType::Emission {
+                self.internal_settle_reward(&mut stake);
+                self.internal_update_stake(&staker_id, &stake);
+            }
+            let claim_history = self.claim_history.get(&stake.stake_id);
+            let last_claimed_at = claim_history
+                .as_ref()
+                .map(|claim| claim.last_claimed_at)
+                .unwrap_or(stake.staked_at);
+            let difference = (current_time - last_claimed_at) / ONE_MINUTE;
+            let unclaimed_reward = u128::from(stake.unclaimed_reward);
+            if difference < 1 && unclaimed_reward == 0 {
+                continue;
+            }
+            let reward = self.internal_pending_reward(&stake);
+            let entry = rewards
+                .entry((stake.ft_account_id.clone(), Contract::payout_receiver(&stake)))
+                .or_insert((0, Vec::new(), Fees::new(&stake.ft_account_id)));
+            entry.0 += reward;
+            entry.2.on_rewards(reward, &self.internal_fee_config(&stake.ft_account_id));
+            let claimed = ClaimedStake {
+                stake_id: stake.stake_id,
+                unclaimed_reward: stake.unclaimed_reward,
+                reward: U128::from(reward),
+                claim_history,
+                claim_count: difference,
+            };
+            //recorded before the transfer and restored by the callback if it fails
+            self.internal_record_claim(&staker_id, &claimed);
+            entry.1.push(claimed);
+        }
+        assert!(!rewards.is_empty(), "No rewards to claim");
+
+        for ((ft_contract, receiver_id), (total, claimed_stakes, fees)) in rewards {
+            log!(
+                "Claiming {} {} for {} stakes",
+                total,
+                ft_contract,
+                claimed_stakes.len()
+            );
+            ext_ft::ft_transfer(
+                receiver_id,
+                U128::from(total - fees.total()),
+                Some("Reward tokens".to_string()),
+                ft_contract,
+                1,
+                BASE_GAS,
+            )
+            .then(this_contract::claim_all_callback(
+                staker_id.clone(),
+                claimed_stakes,
+                fees,
+                env::current_account_id(),
+                0,
+                BASE_GAS,
+            ));
+        }
+    }
+
+    #[private]
+    fn claim_all_callback(
         &mut self,
-        stake_id: StakeId,
-        claim_history: Option<ClaimHistory>,
-        claim_count: u64,
+        staker_id: AccountId,
+        claimed_stakes: Vec<ClaimedStake>,
+        fees: Fees,
     ) {
         if Self::did_promise_succeded() {
-            let claim: ClaimHistory;
-            let current_time = env::block_timestamp() / 1000000000;
-            if claim_history.is_none() {
-                let count = claim_count as u8;
-                claim = ClaimHistory {
-                    last_claimed_at: current_time,
-                    claim_count: count,
-                }
-            } else {
-                claim = ClaimHistory {
-                    last_claimed_at: current_time,
-                    claim_count: claim_history.unwrap().claim_count + 1,
-                }
+            self.internal_collect_fees(&fees);
+            for claimed in claimed_stakes.iter() {
+                self.internal_claim_paid(&staker_id, claimed);
+            }
+        } else {
+            for claimed in claimed_stakes.iter() {
+                self.internal_restore_claim(&staker_id, claimed);
             }
-            self.claim_history.insert(&stake_id, &claim);
+        }
+    }
+
+    fn split_stake(&mut self, stake_id: StakeId, amounts: Vec<U128>) -> Vec<StakeId> {
+        let staker_id: AccountId = env::predecessor_account_id();
+        let mut stake = self.internal_get_stake(&staker_id, stake_id);
+        assert_eq!(
+            stake.staked_by.to_string(),
+            staker_id.to_string(),
+            "Only owner of the tokens can split the stake"
+        );
+        assert!(!amounts.is_empty(), "No amounts to split");
+
+        //rewards accrued before the split stay with the original stake
+        self.internal_leave_pool(&mut stake);
+
+        let apy = self.internal_get_apy(&stake.ft_account_id, &stake.staking_plan);
+        let threshold: u128 = u128::from(apy.min_staking_amount);
+        let split_total: u128 = amounts.iter().map(|amount| u128::from(*amount)).sum();
+        let staked_amount = u128::from(stake.amount);
+        assert!(
+            split_total < staked_amount,
+            "Cannot split more than {} tokens",
+            staked_amount
+        );
+        assert!(
+            staked_amount - split_total >= threshold,
+            "Cannot stake less than {} tokens",
+            threshold
+        );
+        stake.amount = U128::from(staked_amount - split_total);
+        let receipt_total = u128::from(stake.receipt_amount);
+        let mut receipt_left = receipt_total;
+
+        let current_time = env::block_timestamp() / 1000000000;
+        let mut staking_history = self.staking_history.get(&staker_id).unwrap();
+        let mut stake_ids: Vec<StakeId> = Vec::new();
+        for amount in amounts {
+            assert!(
+                u128::from(amount) >= threshold,
+                "Cannot stake less than {} tokens",
+                threshold
+            );
+            let new_stake_id = self.internal_next_stake_id();
+            //receipts are divided in the same proportion as the amount
+            let receipt_amount = Contract::mul_div(receipt_total, u128::from(amount), staked_amount);
+            receipt_left -= receipt_amount;
+            let mut new_stake = Stake {
+                stake_id: new_stake_id,
+                amount,
+                unclaimed_reward: U128::from(0),
+                receipt_amount: U128::from(receipt_amount),
+                ..stake.clone()
+            };
+            //the new stake starts accruing from the split
+            self.claim_history.insert(
+                &new_stake_id,
+                &ClaimHistory {
+                    last_claimed_at: current_time,
+                    claim_count: 0,
+                },
+            );
+            self.internal_join_pool(&mut new_stake);
+            self.internal_track_stake(&staker_id, &new_stake.ft_account_id, &new_stake.staking_plan, true);
+            staking_history.push(new_stake);
+            self.stake_owners.insert(&new_stake_id, &staker_id);
+            stake_ids.push(new_stake_id);
+        }
+        self.internal_save_stakes(&staker_id, &staking_history);
+        stake.receipt_amount = U128::from(receipt_left);
+        self.internal_join_pool(&mut stake);
+        self.internal_update_stake(&staker_id, &stake);
+        emit_nft_mint(
+            &staker_id,
+            stake_ids.iter().map(|id| u128::from(*id).to_string()).collect(),
+        );
+
+        log!(
+            "Staking ID {} split into {:?}",
+            u128::from(stake_id),
+            stake_ids.iter().map(|id| u128::from(*id)).collect::<Vec<u128>>()
+        );
+        stake_ids
+    }
+
+    fn restake(&mut self, stake_id: StakeId, staking_plan: String, duration: u64, with_reward: bool) {
+        let staker_id: AccountId = env::predecessor_account_id();
+        let mut stake = self.internal_get_stake(&staker_id, stake_id);
+        let current_time = env::block_timestamp() / 1000000000;
+
+        assert_eq!(
+            stake.staked_by.to_string(),
+            staker_id.to_string(),
+            "Only owner of the tokens can restake"
+        );
+        //an auto renewing lock never ends, turning it off keeps the lock that is running
+        assert!(
+            !stake.auto_renew,
+            "Turn off auto renewal before restaking"
+        );
+        assert!(
+            current_time >= Contract::unlocks_at(&stake, current_time),
+            "Cannot restake before locked time"
+        );
+
+        //rewards of the old lock are kept at the old rate
+        self.internal_leave_pool(&mut stake);
+        if with_reward {
+            //restaked rewards are paid into the stake
+            let unclaimed_reward = u128::from(stake.unclaimed_reward);
+            self.internal_release_rewards(&stake.ft_account_id, &stake.staking_plan, unclaimed_reward, true);
+            self.internal_accrue_referral(stake.referrer.as_ref(), &stake.ft_account_id, unclaimed_reward);
+            let amount = u128::from(stake.amount) + unclaimed_reward;
+            stake.amount = U128::from(amount);
+            stake.unclaimed_reward = U128::from(0);
+        }
+
+        let apy = self.internal_get_apy(&stake.ft_account_id, &staking_plan);
+        assert!(
+            apy.plan_type != PlanType::Flexible,
+            "Cannot restake into a flexible plan"
+        );
+        self.assert_can_stake(&staker_id, &stake.ft_account_id, &apy);
+        assert!(
+            u128::from(stake.amount)
+                <= self.internal_capacity(&staker_id, &stake.ft_account_id, &staking_plan, Some(stake_id)),
+            "Staking cap reached"
+        );
+        let threshold: u128 = u128::from(apy.min_staking_amount);
+        assert!(
+            u128::from(stake.amount) >= threshold,
+            "Cannot stake less than {} tokens",
+            threshold
+        );
+        Contract::assert_plan_open(&apy, current_time);
+        let duration = Contract::plan_duration(&apy, current_time, duration);
+        assert!(
+            apy.matures_at.is_some() || duration / ONE_MINUTE >= apy.min_duration.into(),
+            "Invalid Duration"
+        );
+
+        self.internal_move_stake_plan(&staker_id, &stake, &staking_plan);
+        stake.staking_plan = staking_plan;
+        stake.duration = duration;
+        stake.staked_at = current_time;
+        stake.compounded_at = current_time;
+        self.internal_join_pool(&mut stake);
+        self.internal_update_stake(&staker_id, &stake);
+
+        log!(
+            "Staking ID {} restaked on {} by {}",
+            u128::from(stake_id),
+            stake.staking_plan,
+            staker_id
+        );
+    }
+
+    fn set_auto_renew(&mut self, stake_id: StakeId, auto_renew: bool) {
+        let staker_id: AccountId = env::predecessor_account_id();
+        let mut stake = self.internal_get_stake(&staker_id, stake_id);
+        assert_eq!(
+            stake.staked_by.to_string(),
+            staker_id.to_string(),
+            "Only owner of the tokens can change auto renewal"
+        );
+        let apy = self.internal_get_apy(&stake.ft_account_id, &stake.staking_plan);
+        assert!(
+            !auto_renew || apy.matures_at.is_none(),
+            "Stakes of a plan with a maturity date cannot auto renew"
+        );
+        if stake.auto_renew && !auto_renew {
+            //the lock that is running now still has to end
+            let current_time = env::block_timestamp() / 1000000000;
+            stake.duration = Contract::unlocks_at(&stake, current_time) - stake.staked_at;
+        }
+        stake.auto_renew = auto_renew;
+        self.internal_update_stake(&staker_id, &stake);
+    }
+
+    fn extend_lock(&mut self, stake_id: StakeId, new_plan: String, duration: Option<u64>) {
+        let staker_id: AccountId = env::predecessor_account_id();
+        let mut stake = self.internal_get_stake(&staker_id, stake_id);
+        assert_eq!(
+            stake.staked_by.to_string(),
+            staker_id.to_string(),
+            "Only owner of the tokens can extend the lock"
+        );
+
+        let old_apy = self.internal_get_apy(&stake.ft_account_id, &stake.staking_plan);
+        let new_apy = self.internal_get_apy(&stake.ft_account_id, &new_plan);
+        assert!(
+            new_apy.min_duration > old_apy.min_duration && new_apy.plan_type != PlanType::Flexible,
+            "Can only move to a longer staking plan"
+        );
+        self.assert_can_stake(&staker_id, &stake.ft_account_id, &new_apy);
+        let threshold: u128 = u128::from(new_apy.min_staking_amount);
+        assert!(
+            u128::from(stake.amount) >= threshold,
+            "Cannot stake less than {} tokens",
+            threshold
+        );
+        let current_time = env::block_timestamp() / 1000000000;
+        Contract::assert_plan_open(&new_apy, current_time);
+        assert!(
+            !stake.auto_renew || new_apy.matures_at.is_none(),
+            "Stakes of a plan with a maturity date cannot auto renew"
+        );
+        //without a duration the lock is extended to the minimum of the new plan
+        let new_duration = Contract::plan_duration(
+            &new_apy,
+            stake.staked_at,
+            duration.unwrap_or(new_apy.min_duration as u64 * ONE_MINUTE),
+        );
+        assert!(
+            new_duration >= stake.duration,
+            "Cannot shorten the locked time"
+        );
+        assert!(
+            new_apy.matures_at.is_some() || new_duration / ONE_MINUTE >= new_apy.min_duration.into(),
+            "Invalid Duration"
+        );
+
+        //rewards accrued so far are kept at the old rate
+        self.internal_leave_pool(&mut stake);
+        assert!(
+            u128::from(stake.amount)
+                <= self.internal_capacity(&staker_id, &stake.ft_account_id, &new_plan, Some(stake_id)),
+            "Staking cap reached"
+        );
+
+        self.internal_move_stake_plan(&staker_id, &stake, &new_plan);
+        stake.staking_plan = new_plan;
+        stake.duration = new_duration;
+        stake.compounded_at = current_time;
+        self.internal_join_pool(&mut stake);
+        self.internal_update_stake(&staker_id, &stake);
+
+        log!(
+            "Staking ID {} moved from {} to {}",
+            u128::from(stake_id),
+            old_apy.apy_key,
+            stake.staking_plan
+        );
+    }
+
+    fn set_beneficiary(&mut self, stake_id: StakeId, beneficiary: Option<AccountId>) {
+        let staker_id: AccountId = env::predecessor_account_id();
+        //rewards of denied accounts are held back, they cannot be sent elsewhere either
+        self.assert_not_denied(&staker_id);
+        let mut stake = self.internal_get_stake(&staker_id, stake_id);
+        assert_eq!(
+            stake.staked_by.to_string(),
+            staker_id.to_string(),
+            "Only owner of the tokens can change the beneficiary"
+        );
+        stake.beneficiary = beneficiary;
+        self.internal_update_stake(&staker_id, &stake);
+    }
+
+    fn set_operator(&mut self, stake_id: StakeId, operator: Option<AccountId>) {
+        let staker_id: AccountId = env::predecessor_account_id();
+        let mut stake = self.internal_get_stake(&staker_id, stake_id);
+        assert_eq!(
+            stake.staked_by.to_string(),
+            staker_id.to_string(),
+            "Only owner of the tokens can change the operator"
+        );
+        stake.operator = operator;
+        self.internal_update_stake(&staker_id, &stake);
+    }
+
+    #[payable]
+    fn transfer_stake(&mut self, stake_id: StakeId, receiver_id: AccountId) {
+        near_sdk::assert_one_yocto();
+        let sender_id: AccountId = env::predecessor_account_id();
+        self.internal_transfer_stake(&sender_id, &receiver_id, stake_id);
+    }
+
+    fn claim_referral_rewards(&mut self, ft_account_id: AccountId) {
+        let referrer_id: AccountId = env::predecessor_account_id();
+        self.assert_not_denied(&referrer_id);
+        let key = (referrer_id.clone(), ft_account_id.clone());
+        let amount = self.referral_rewards.get(&key).unwrap_or(U128::from(0));
+        assert!(u128::from(amount) > 0, "No referral rewards to claim");
+        //taken out now and given back if the transfer fails
+        self.referral_rewards.remove(&key);
+
+        ext_ft::ft_transfer(
+            referrer_id.clone(),
+            amount,
+            Some("Referral rewards".to_string()),
+            ft_account_id.clone(),
+            1,
+            BASE_GAS,
+        )
+        .then(this_contract::referral_callback(
+            referrer_id,
+            ft_account_id,
+            amount,
+            env::current_account_id(),
+            0,
+            BASE_GAS,
+        ));
+    }
+
+    #[private]
+    fn referral_callback(&mut self, referrer_id: AccountId, ft_account_id: AccountId, amount: U128) {
+        if !Self::did_promise_succeded() {
+            self.internal_add_referral_reward(&referrer_id, &ft_account_id, u128::from(amount));
+            log!("Restored {} referral rewards to {}", u128::from(amount), referrer_id);
+        }
+    }
+
+    #[private]
+    fn treasury_callback(&mut self, token: AccountId, amount: U128) {
+        if !Self::did_promise_succeded() {
+            let mut treasury = self.treasury.get(&token).unwrap();
+            treasury.balance = U128::from(u128::from(treasury.balance) + u128::from(amount));
+            self.treasury.insert(&token, &treasury);
+            log!("Restored {} to the treasury of {}", u128::from(amount), token);
         }
     }
 
@@ -388,6 +1323,9 @@ impl FTActionsReceiver for Contract {
                 panic!("already claimed drop");
             }
         }
+        if let Some(min_tier) = self.airdrop_tiers.get(&ft_contract_id) {
+            self.assert_tier(&account_id, &min_tier);
+        }
         ext_ft::ft_transfer(
             account_id.clone(),
             U128::from(TEN_THOUSAND),
@@ -412,8 +1350,7 @@ impl FTActionsReceiver for Contract {
                 self.registered_members.insert(&ft_contract_id, &members);
                 // log!("in if acct_id =  {} reg_mem = {:?}",account_id,members);
             } else {
-                let mut members: Vec<AccountId> = Vec::new();
-                members.push(account_id);
+                let members: Vec<AccountId> = vec![account_id];
                 self.registered_members.insert(&ft_contract_id, &members);
             }
         }
@@ -0,0 +1,185 @@
+use crate::*;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct GovernanceConfig {
+    pub ft_account_id: AccountId, //only stakes of this token give voting power
+    pub voting_period: u64, //in seconds
+    pub quorum: U128, //voting power that has to take part for a proposal to pass
+    pub proposal_threshold: U128, //voting power needed to create a proposal
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde", tag = "kind", rename_all = "snake_case")]
+pub enum ProposalKind {
+    SetApy { ft_account_id: AccountId, apy: APY },
+    ApproveFt { ft: FT, ft_apy: Vec<APY> },
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde", rename_all = "snake_case")]
+pub enum ProposalStatus {
+    Voting,
+    Executed,
+    Rejected,
+    Failed, //passed but its action was no longer valid when executed
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Proposal {
+    pub id: u64,
+    pub proposer: AccountId,
+    pub description: String,
+    pub kind: ProposalKind,
+    pub snapshot_at: u64, //votes are weighted by the voting power at this time
+    pub voting_ends_at: u64,
+    pub votes_for: U128,
+    pub votes_against: U128,
+    pub status: ProposalStatus,
+}
+
+#[near_bindgen]
+impl Contract {
+    pub fn create_proposal(&mut self, description: String, kind: ProposalKind) -> u64 {
+        let config = self.governance.clone().expect("Governance is not enabled");
+        let proposer = env::predecessor_account_id();
+        let current_time = env::block_timestamp() / 1000000000;
+        let power = self.internal_voting_power(&proposer, &config.ft_account_id, current_time);
+        assert!(
+            power > 0 && power >= u128::from(config.proposal_threshold),
+            "Not enough voting power to create a proposal"
+        );
+        if let Some(error) = self.proposal_error(&kind) {
+            panic!("{}", error);
+        }
+
+        self.proposal_nonce += 1;
+        let proposal = Proposal {
+            id: self.proposal_nonce,
+            proposer,
+            description,
+            kind,
+            //stakes made in the block of the proposal do not count
+            snapshot_at: current_time.saturating_sub(1),
+            voting_ends_at: current_time + config.voting_period,
+            votes_for: U128::from(0),
+            votes_against: U128::from(0),
+            status: ProposalStatus::Voting,
+        };
+        self.proposals.insert(&proposal.id, &proposal);
+        log!("Proposal {} created by {}", proposal.id, proposal.proposer);
+        proposal.id
+    }
+
+    pub fn vote(&mut self, proposal_id: u64, approve: bool) {
+        let config = self.governance.clone().expect("Governance is not enabled");
+        let voter = env::predecessor_account_id();
+        let mut proposal = self.proposals.get(&proposal_id).expect("No proposal with this id");
+        let current_time = env::block_timestamp() / 1000000000;
+        assert!(
+            proposal.status == ProposalStatus::Voting && current_time < proposal.voting_ends_at,
+            "Voting on this proposal has ended"
+        );
+        assert!(
+            self.proposal_votes.get(&(proposal_id, voter.clone())).is_none(),
+            "Already voted on this proposal"
+        );
+        //stakes made after the proposal was created do not count
+        let power = self.internal_voting_power(&voter, &config.ft_account_id, proposal.snapshot_at);
+        assert!(power > 0, "No voting power at the proposal snapshot");
+
+        if approve {
+            proposal.votes_for = U128::from(u128::from(proposal.votes_for) + power);
+        } else {
+            proposal.votes_against = U128::from(u128::from(proposal.votes_against) + power);
+        }
+        self.proposals.insert(&proposal_id, &proposal);
+        self.proposal_votes.insert(&(proposal_id, voter.clone()), &approve);
+        log!("{} voted {} on proposal {} with {}", voter, approve, proposal_id, power);
+    }
+
+    //anyone can finalize a proposal once its voting period is over
+    pub fn execute_proposal(&mut self, proposal_id: u64) -> ProposalStatus {
+        let config = self.governance.clone().expect("Governance is not enabled");
+        let mut proposal = self.proposals.get(&proposal_id).expect("No proposal with this id");
+        let current_time = env::block_timestamp() / 1000000000;
+        assert!(
+            proposal.status == ProposalStatus::Voting,
+            "Proposal is already finalized"
+        );
+        assert!(
+            current_time >= proposal.voting_ends_at,
+            "Voting on this proposal has not ended"
+        );
+
+        let votes_for = u128::from(proposal.votes_for);
+        let votes_against = u128::from(proposal.votes_against);
+        if votes_for + votes_against >= u128::from(config.quorum) && votes_for > votes_against {
+            //the owner may have changed what the action depends on while it was voted on
+            if let Some(error) = self.proposal_error(&proposal.kind) {
+                log!("Proposal {} cannot be executed: {}", proposal_id, error);
+                proposal.status = ProposalStatus::Failed;
+            } else {
+                match proposal.kind.clone() {
+                    ProposalKind::SetApy { ft_account_id, apy } => self.internal_set_apy(&ft_account_id, apy),
+                    ProposalKind::ApproveFt { ft, ft_apy } => self.internal_approve_ft(ft, ft_apy),
+                }
+                proposal.status = ProposalStatus::Executed;
+            }
+        } else {
+            proposal.status = ProposalStatus::Rejected;
+        }
+        self.proposals.insert(&proposal_id, &proposal);
+        log!("Proposal {} {:?}", proposal_id, proposal.status);
+        proposal.status
+    }
+
+    pub fn get_proposal(&self, proposal_id: u64) -> Option<Proposal> {
+        self.proposals.get(&proposal_id)
+    }
+
+    pub fn get_proposals(&self, from_index: Option<u64>, limit: Option<u64>) -> Vec<Proposal> {
+        let start = from_index.unwrap_or(1).max(1);
+        let end = min(start + limit.unwrap_or(self.proposal_nonce), self.proposal_nonce + 1);
+        (start..end).filter_map(|id| self.proposals.get(&id)).collect()
+    }
+
+    pub fn get_governance_config(&self) -> Option<GovernanceConfig> {
+        self.governance.clone()
+    }
+
+    pub fn get_vote(&self, proposal_id: u64, account_id: AccountId) -> Option<bool> {
+        self.proposal_votes.get(&(proposal_id, account_id))
+    }
+}
+
+impl Contract {
+    //why the action of a proposal would panic, checked when it is created and again before it is executed
+    fn proposal_error(&self, kind: &ProposalKind) -> Option<&'static str> {
+        match kind {
+            ProposalKind::SetApy { ft_account_id, apy } => {
+                let ft = match self.approved_fts.get(ft_account_id) {
+                    Some(ft) => ft,
+                    None => return Some("FT is not approved"),
+                };
+                let plan_type = ft
+                    .apy_against_duration
+                    .and_then(|apy_map| apy_map.get(&apy.apy_key).map(|current| current.plan_type.clone()));
+                if plan_type.is_some_and(|plan_type| plan_type != apy.plan_type) {
+                    return Some("Cannot change the type of a staking plan");
+                }
+                None
+            }
+            ProposalKind::ApproveFt { ft, .. } => {
+                if self.approved_ft_token_ids.contains(&ft.account_id) {
+                    return Some("FT is already approved");
+                }
+                if ft.decimals.is_some_and(|decimals| decimals > 38) {
+                    return Some("Invalid decimals");
+                }
+                None
+            }
+        }
+    }
+}
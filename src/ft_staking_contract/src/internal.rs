@@ -1,4 +1,5 @@
 use crate::*;
+use crate::events::{emit_nft_transfer, emit_stake_transfer};
 
 
 impl Contract{
@@ -9,6 +10,7 @@ impl Contract{
         ft_apy: Vec<APY>,
     ) {
         for mut ft in approved_ft_token_ids {
+            Contract::assert_valid_decimals(ft.decimals);
             let  apy_map: HashMap<APYKey, APY> = Contract::insert_apy(ft_apy.clone());
             ft.apy_against_duration = Some(apy_map);
             set.insert(&ft.account_id, &ft);
@@ -16,6 +18,11 @@ impl Contract{
         }
     }
 
+    //amounts are kept in u128, 10^38 is the largest power of ten that fits
+    pub(crate) fn assert_valid_decimals(decimals: Option<u8>) {
+        assert!(decimals.is_none_or(|decimals| decimals <= 38), "Invalid decimals");
+    }
+
     pub(crate) fn insert_apy(ft_apy: Vec<APY>)-> HashMap<APYKey,APY>{
         let mut temp :HashMap<APYKey,APY> = HashMap::new();
 
@@ -26,8 +33,441 @@ impl Contract{
         temp
     }
 
+    //shared by the owner methods and executed governance proposals
+    pub(crate) fn internal_set_apy(&mut self, ft_account_id: &AccountId, apy: APY) {
+        let mut ft = self.approved_fts.get(ft_account_id).expect("FT is not approved");
+        let mut apy_map = ft.apy_against_duration.unwrap_or_default();
+        if let Some(current) = apy_map.get(&apy.apy_key) {
+            //stakes of the plan keep their state, so the way they earn cannot change
+            assert!(
+                current.plan_type == apy.plan_type,
+                "Cannot change the type of a staking plan"
+            );
+        }
+        log!("Staking plan {} of {} updated", apy.apy_key, ft_account_id);
+        apy_map.insert(apy.apy_key.clone(), apy);
+        ft.apy_against_duration = Some(apy_map);
+        self.approved_fts.insert(ft_account_id, &ft);
+    }
+
+    pub(crate) fn internal_approve_ft(&mut self, ft: FT, ft_apy: Vec<APY>) {
+        assert!(
+            !self.approved_ft_token_ids.contains(&ft.account_id),
+            "FT is already approved"
+        );
+        log!("{} approved for staking", ft.account_id);
+        Contract::add_fts(
+            vec![ft],
+            &mut self.approved_fts,
+            &mut self.approved_ft_token_ids,
+            ft_apy,
+        );
+    }
+
+    //deposits into a plan are taken between opens_at and closes_at and before its maturity date
+    pub(crate) fn assert_plan_open(apy: &APY, current_time: u64) {
+        assert!(
+            apy.opens_at.is_none_or(|opens_at| current_time >= opens_at),
+            "Staking plan is not open yet"
+        );
+        assert!(
+            apy.closes_at.is_none_or(|closes_at| current_time < closes_at)
+                && apy.matures_at.is_none_or(|matures_at| current_time < matures_at),
+            "Staking plan is closed"
+        );
+    }
+
+    //lock of a stake in a plan, a plan with a maturity date unlocks all its stakes at once
+    pub(crate) fn plan_duration(apy: &APY, staked_at: u64, duration: u64) -> u64 {
+        match apy.matures_at {
+            Some(matures_at) => matures_at.saturating_sub(staked_at),
+            None => duration,
+        }
+    }
+
+    pub(crate) fn internal_get_apy(&self, ft_account_id: &AccountId, staking_plan: &str) -> APY {
+        let ft = self.approved_fts.get(ft_account_id).expect("FT is not approved");
+        let apy_map = ft.apy_against_duration.expect("No staking plans for this FT");
+        apy_map.get(staking_plan).expect("Invalid staking plan").clone()
+    }
+
+    pub(crate) fn internal_get_stake(&self, account_id: &AccountId, stake_id: StakeId) -> Stake {
+        self.staking_history
+            .get(account_id)
+            .expect("This user has not staked yet.")
+            .into_iter()
+            .find(|i| i.stake_id == stake_id)
+            .expect("No staking data with this id found for caller")
+    }
+
+    //finds a stake of the caller, or a stake the caller is the operator of, together with its staker
+    pub(crate) fn internal_get_managed_stake(&self, caller_id: &AccountId, stake_id: StakeId) -> (AccountId, Stake) {
+        if let Some(staker_id) = self.stake_owners.get(&stake_id) {
+            if &staker_id != caller_id {
+                let stake = self.internal_get_stake(&staker_id, stake_id);
+                if stake.operator.as_ref() == Some(caller_id) {
+                    return (staker_id, stake);
+                }
+            }
+        }
+        (caller_id.clone(), self.internal_get_stake(caller_id, stake_id))
+    }
+
+    //owner of the stake NFT, stakes made before the NFTs were introduced belong to staked_by
+    pub(crate) fn internal_stake_owner(&self, stake: &Stake) -> AccountId {
+        self.stake_owners
+            .get(&stake.stake_id)
+            .unwrap_or_else(|| stake.staked_by.clone())
+    }
+
+    //rewards and principal go to the beneficiary when the staker has set one
+    pub(crate) fn payout_receiver(stake: &Stake) -> AccountId {
+        stake.beneficiary.clone().unwrap_or_else(|| stake.staked_by.clone())
+    }
+
+    pub(crate) fn internal_next_stake_id(&mut self) -> StakeId {
+        self.staking_nonce = self
+            .staking_nonce
+            .checked_add(1)
+            .expect("Exceeded u128 capacity");
+        U128::from(self.staking_nonce)
+    }
+
+    pub(crate) fn internal_update_stake(&mut self, account_id: &AccountId, stake: &Stake) {
+        let mut staking_history = self.staking_history.get(account_id).unwrap();
+        let index = staking_history
+            .iter()
+            .position(|i| i.stake_id == stake.stake_id)
+            .expect("No staking data with this id found for caller");
+        staking_history[index] = stake.clone();
+        self.internal_save_stakes(account_id, &staking_history);
+    }
+
+    //what a plan pays each minute as a fraction of the amount, interest_rate / 10 percent over min_duration minutes
+    pub(crate) fn minute_rate(apy: &APY) -> (u128, u128) {
+        (apy.interest_rate as u128, 1000 * apy.min_duration as u128)
+    }
+
+    //reward of a simple interest plan for the given number of minutes
+    pub(crate) fn simple_reward(amount: u128, apy: &APY, periods: u64) -> u128 {
+        let (rate, scale) = Contract::minute_rate(apy);
+        Contract::mul_div(amount, rate * periods as u128, scale)
+    }
+
+    //amount after compounding the minute rate of the plan for the given number of minutes, None when it overflows
+    pub(crate) fn compound_amount(amount: u128, apy: &APY, periods: u64) -> Option<u128> {
+        let (rate, scale) = Contract::minute_rate(apy);
+        let mut factor = REWARD_PRECISION;
+        let mut base = REWARD_PRECISION.checked_add(Self::checked_mul_div(REWARD_PRECISION, rate, scale)?)?;
+        let mut n = periods;
+        while n > 0 {
+            if n & 1 == 1 {
+                factor = Self::checked_mul_div(factor, base, REWARD_PRECISION)?;
+            }
+            n >>= 1;
+            if n > 0 {
+                base = Self::checked_mul_div(base, base, REWARD_PRECISION)?;
+            }
+        }
+        Self::checked_mul_div(amount, factor, REWARD_PRECISION)
+    }
+
+    //a * b / d without overflowing when a is a token amount
+    pub(crate) fn mul_div(a: u128, b: u128, d: u128) -> u128 {
+        Self::checked_mul_div(a, b, d).expect("Compounded amount overflow")
+    }
+
+    pub(crate) fn checked_mul_div(a: u128, b: u128, d: u128) -> Option<u128> {
+        (a / d)
+            .checked_mul(b)
+            .and_then(|x| x.checked_add((a % d).checked_mul(b)? / d))
+    }
+
+    //adds the rewards accrued since the last compounding to the amount of an auto-compounding stake
+    pub(crate) fn internal_compound_stake(&self, stake: &mut Stake) {
+        let apy = self.internal_get_apy(&stake.ft_account_id, &stake.staking_plan);
+        if !apy.auto_compound {
+            return;
+        }
+        let current_time = env::block_timestamp() / 1000000000;
+        let periods = (current_time - stake.compounded_at) / ONE_MINUTE;
+        if periods == 0 {
+            return;
+        }
+        let amount = Contract::compound_amount(u128::from(stake.amount), &apy, periods)
+            .expect("Compounded amount overflows");
+        stake.amount = U128::from(amount);
+        stake.compounded_at += periods * ONE_MINUTE;
+    }
+
+    //moves the rewards accrued so far into unclaimed_reward so the stake can change without losing them
+    pub(crate) fn internal_settle_reward(&mut self, stake: &mut Stake) {
+        let apy = self.internal_get_apy(&stake.ft_account_id, &stake.staking_plan);
+        if apy.auto_compound {
+            self.internal_compound_stake(stake);
+        } else {
+            let accrued = self.internal_pending_reward(stake) - u128::from(stake.unclaimed_reward);
+            stake.unclaimed_reward = U128::from(u128::from(stake.unclaimed_reward) + accrued);
+            if apy.plan_type == PlanType::Emission {
+                let pool = self.internal_get_emission_pool(&stake.ft_account_id);
+                stake.reward_debt = U128::from(pool.accumulated(u128::from(stake.weight)));
+                self.emission_pools.insert(&stake.ft_account_id, &pool);
+            }
+        }
+        //written for every plan type, a stake moved to a fixed plan earns from here
+        let current_time = env::block_timestamp() / 1000000000;
+        let (last_claimed_at, claim_count) = self
+            .claim_history
+            .get(&stake.stake_id)
+            .map(|claim| (claim.last_claimed_at, claim.claim_count))
+            .unwrap_or((stake.staked_at, 0));
+        //simple interest is settled for whole minutes only, the partial minute keeps counting
+        let last_claimed_at = if apy.plan_type == PlanType::Fixed && !apy.auto_compound {
+            last_claimed_at + (current_time - last_claimed_at) / ONE_MINUTE * ONE_MINUTE
+        } else {
+            current_time
+        };
+        let claim = ClaimHistory {
+            last_claimed_at,
+            claim_count,
+        };
+        self.claim_history.insert(&stake.stake_id, &claim);
+    }
+
+    //moves a stake with its accrued rewards and claim history to another account
+    pub(crate) fn internal_transfer_stake(
+        &mut self,
+        sender_id: &AccountId,
+        receiver_id: &AccountId,
+        stake_id: StakeId,
+    ) -> Stake {
+        assert_ne!(sender_id, receiver_id, "Cannot transfer a stake to yourself");
+        //the stake would take its held back rewards to an account that is not denied
+        self.assert_not_denied(sender_id);
+        let stake = self.internal_get_stake(sender_id, stake_id);
+        assert_eq!(
+            self.internal_stake_owner(&stake).to_string(),
+            sender_id.to_string(),
+            "Only owner of the tokens can transfer the stake"
+        );
+        //the stake leaves the totals while the caps of the receiver are checked, so only its own caps count
+        let amount = u128::from(stake.amount);
+        self.internal_remove_staked(&stake.ft_account_id, &stake.staking_plan, amount);
+        let capacity = self.internal_capacity(receiver_id, &stake.ft_account_id, &stake.staking_plan, None);
+        assert!(capacity >= amount, "Staking cap of the receiver reached");
+        self.internal_add_staked(&stake.ft_account_id, &stake.staking_plan, amount);
+        self.internal_move_stake(sender_id, receiver_id, stake)
+    }
+
+    //moves a stake of the sender to the receiver without any checks, they are done by the callers
+    pub(crate) fn internal_move_stake(&mut self, sender_id: &AccountId, receiver_id: &AccountId, mut stake: Stake) -> Stake {
+        let stake_id = stake.stake_id;
+        let mut sender_stakes = self.staking_history.get(sender_id).unwrap_or_default();
+        sender_stakes.retain(|i| i.stake_id != stake_id);
+        self.internal_save_stakes(sender_id, &sender_stakes);
+        self.internal_move_receipt(sender_id, receiver_id, u128::from(stake.receipt_amount));
+        self.internal_track_stake(sender_id, &stake.ft_account_id, &stake.staking_plan, false);
+        self.internal_track_stake(receiver_id, &stake.ft_account_id, &stake.staking_plan, true);
+
+        //beneficiary and operator were chosen by the previous owner
+        stake.staked_by = receiver_id.clone();
+        stake.beneficiary = None;
+        stake.operator = None;
+        let mut receiver_stakes = self.staking_history.get(receiver_id).unwrap_or_default();
+        receiver_stakes.push(stake.clone());
+        self.internal_save_stakes(receiver_id, &receiver_stakes);
+        self.stake_owners.insert(&stake_id, receiver_id);
+
+        emit_stake_transfer(stake_id, sender_id, receiver_id);
+        emit_nft_transfer(sender_id, receiver_id, vec![u128::from(stake_id).to_string()]);
+        stake
+    }
+
+    //adds tokens to an existing stake, the lock start moves to the amount weighted average of both deposits
+    pub(crate) fn internal_top_up(&mut self, sender_id: &AccountId, stake_id: StakeId, amount: U128) -> u128 {
+        let mut stake = self.internal_get_stake(sender_id, stake_id);
+        let calling_contrat = env::predecessor_account_id();
+        assert!(
+            self.approved_ft_token_ids.contains(&calling_contrat),
+            "Only approved FT can be staked"
+        );
+        assert_eq!(
+            stake.ft_account_id, calling_contrat,
+            "Cannot top up a stake of another FT"
+        );
+        assert!(u128::from(amount) > 0, "Cannot stake 0 tokens");
+        let accepted =
+            self.internal_accept_deposit(sender_id, &calling_contrat, &stake.staking_plan, u128::from(amount));
+        let unused = u128::from(amount) - accepted;
+        let amount = U128::from(self.internal_take_deposit_fee(&calling_contrat, accepted));
+
+        let current_time = env::block_timestamp() / 1000000000;
+        let apy = self.internal_get_apy(&stake.ft_account_id, &stake.staking_plan);
+        Contract::assert_plan_open(&apy, current_time);
+        self.assert_can_stake(sender_id, &stake.ft_account_id, &apy);
+
+        //rewards accrued before the top up are kept for the old amount
+        self.internal_leave_pool(&mut stake);
+
+        let old_amount = u128::from(stake.amount);
+        let added_amount = u128::from(amount);
+        let total = old_amount + added_amount;
+        let staked_at = Contract::mul_div(old_amount, stake.staked_at as u128, total)
+            + Contract::mul_div(added_amount, current_time as u128, total);
+        stake.staked_at = staked_at as u64;
+        stake.duration = Contract::plan_duration(&apy, stake.staked_at, stake.duration);
+        stake.amount = U128::from(total);
+        let minted = self.internal_mint_receipt(&calling_contrat, sender_id, added_amount);
+        stake.receipt_amount = U128::from(u128::from(stake.receipt_amount) + minted);
+        self.internal_join_pool(&mut stake);
+        self.internal_update_stake(sender_id, &stake);
+
+        log!(
+            "{:?} added to Staking ID {} by {}",
+            amount,
+            u128::from(stake_id),
+            sender_id
+        );
+        unused
+    }
+
+    //rewards transferred to the staker of a stake, its referrer gets a share
+    pub(crate) fn internal_pay_rewards(&mut self, staker_id: &AccountId, stake: &Stake, reward: u128) {
+        self.internal_release_rewards(&stake.ft_account_id, &stake.staking_plan, reward, true);
+        self.internal_add_claimed(staker_id, &stake.ft_account_id, reward);
+        self.internal_accrue_referral(stake.referrer.as_ref(), &stake.ft_account_id, reward);
+    }
+
+    //unclaimed rewards of a stake that was withdrawn, the ones of denied accounts were held back
+    pub(crate) fn internal_withdrawn_rewards(&mut self, staker_id: &AccountId, stake: &Stake) {
+        let reward = u128::from(stake.unclaimed_reward);
+        if self.denylist.contains(staker_id) {
+            self.internal_release_rewards(&stake.ft_account_id, &stake.staking_plan, reward, false);
+        } else {
+            self.internal_pay_rewards(staker_id, stake, reward);
+        }
+    }
+
+    fn internal_find_stake(&self, staker_id: &AccountId, stake_id: StakeId) -> Option<Stake> {
+        self.staking_history
+            .get(staker_id)
+            .and_then(|stakes| stakes.into_iter().find(|i| i.stake_id == stake_id))
+    }
+
+    //takes the claimed rewards out of the stake before they are transferred so they cannot be claimed twice
+    pub(crate) fn internal_record_claim(&mut self, staker_id: &AccountId, claimed: &ClaimedStake) {
+        let paid = u128::from(claimed.unclaimed_reward);
+        let mut last_claimed_at = env::block_timestamp() / 1000000000;
+        if let Some(mut stake) = self.internal_find_stake(staker_id, claimed.stake_id) {
+            //the minutes paid for are checkpointed, the partial minute keeps counting
+            last_claimed_at = claimed
+                .claim_history
+                .as_ref()
+                .map(|claim| claim.last_claimed_at)
+                .unwrap_or(stake.staked_at)
+                + claimed.claim_count * ONE_MINUTE;
+            if paid > 0 {
+                let remaining = u128::from(stake.unclaimed_reward).saturating_sub(paid);
+                stake.unclaimed_reward = U128::from(remaining);
+                self.internal_update_stake(staker_id, &stake);
+            }
+        }
+        let claim: ClaimHistory;
+        if let Some(claim_history) = &claimed.claim_history {
+            claim = ClaimHistory {
+                last_claimed_at,
+                claim_count: claim_history.claim_count + 1,
+            }
+        } else {
+            claim = ClaimHistory {
+                last_claimed_at,
+                claim_count: claimed.claim_count as u8,
+            }
+        }
+        self.claim_history.insert(&claimed.stake_id, &claim);
+    }
+
+    //a claim that was transferred, its rewards are counted as paid
+    pub(crate) fn internal_claim_paid(&mut self, staker_id: &AccountId, claimed: &ClaimedStake) {
+        if let Some(stake) = self.internal_find_stake(staker_id, claimed.stake_id) {
+            self.internal_pay_rewards(staker_id, &stake, u128::from(claimed.reward));
+        }
+    }
+
+    //a claim whose transfer failed, the rewards and the checkpoint go back to what they were
+    pub(crate) fn internal_restore_claim(&mut self, staker_id: &AccountId, claimed: &ClaimedStake) {
+        let paid = u128::from(claimed.unclaimed_reward);
+        if let Some(mut stake) = self.internal_find_stake(staker_id, claimed.stake_id) {
+            if paid > 0 {
+                stake.unclaimed_reward = U128::from(u128::from(stake.unclaimed_reward) + paid);
+                self.internal_update_stake(staker_id, &stake);
+            }
+        }
+        match &claimed.claim_history {
+            Some(claim_history) => self.claim_history.insert(&claimed.stake_id, claim_history),
+            None => self.claim_history.remove(&claimed.stake_id),
+        };
+    }
+
+    //end of the current lock, a stake with auto_renew is locked again each time its duration passes
+    pub(crate) fn unlocks_at(stake: &Stake, current_time: u64) -> u64 {
+        Contract::lock_end(stake.staked_at, stake.duration, stake.auto_renew, current_time)
+    }
+
+    pub(crate) fn lock_end(staked_at: u64, duration: u64, auto_renew: bool, current_time: u64) -> u64 {
+        if !auto_renew || duration == 0 || current_time < staked_at {
+            return staked_at + duration;
+        }
+        let cycles = (current_time - staked_at) / duration + 1;
+        staked_at + duration * cycles
+    }
+
+    pub(crate) fn internal_pending_reward(&self, stake: &Stake) -> u128 {
+        let apy = self.internal_get_apy(&stake.ft_account_id, &stake.staking_plan);
+        if apy.auto_compound {
+            let mut compounded = stake.clone();
+            self.internal_compound_stake(&mut compounded);
+            return u128::from(compounded.amount) - u128::from(stake.amount);
+        }
+        if apy.plan_type == PlanType::Emission {
+            let pool = self.internal_get_emission_pool(&stake.ft_account_id);
+            let accumulated = pool.accumulated(u128::from(stake.weight));
+            return u128::from(stake.unclaimed_reward) + accumulated - u128::from(stake.reward_debt);
+        }
+        let current_time = env::block_timestamp() / 1000000000;
+        let last_claimed_at = self
+            .claim_history
+            .get(&stake.stake_id)
+            .map(|claim| claim.last_claimed_at)
+            .unwrap_or(stake.staked_at);
+        let periods = (current_time - last_claimed_at) / ONE_MINUTE;
+        u128::from(stake.unclaimed_reward)
+            + Contract::simple_reward(u128::from(stake.amount), &apy, periods)
+    }
+
+    //what claim_reward pays for a stake now, nothing while it cannot be claimed
+    pub(crate) fn internal_claimable_reward(&self, stake: &Stake, current_time: u64) -> u128 {
+        let apy = self.internal_get_apy(&stake.ft_account_id, &stake.staking_plan);
+        if apy.auto_compound || self.denylist.contains(&self.internal_stake_owner(stake)) {
+            return 0;
+        }
+        let last_claimed_at = self
+            .claim_history
+            .get(&stake.stake_id)
+            .map(|claim| claim.last_claimed_at)
+            .unwrap_or(stake.staked_at);
+        //emission rewards are settled before the check, so they can be claimed within the minute
+        if apy.plan_type != PlanType::Emission
+            && (current_time - last_claimed_at) / ONE_MINUTE < 1
+            && u128::from(stake.unclaimed_reward) == 0
+        {
+            return 0;
+        }
+        self.internal_pending_reward(stake)
+    }
+
     pub(crate) fn is_owner(&self) -> bool {
-        &env::predecessor_account_id() == &self.owner_id
+        env::predecessor_account_id() == self.owner_id
     }
     pub(crate) fn assert_owner(&self) {
         assert!(self.is_owner(), "Owner's method");
@@ -1,3 +1,5 @@
+use near_contract_standards::fungible_token::metadata::FungibleTokenMetadata;
+use near_contract_standards::fungible_token::FungibleToken;
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
 use near_sdk::collections::{LookupMap, UnorderedSet};
 use near_sdk::json_types::U128;
@@ -9,8 +11,36 @@ use std::collections::HashMap;
 pub type APYKey = String; //6 months =  6months
 pub type StakeId = U128;
 
+pub(crate) const ONE_MINUTE: u64 = 60; //reward accrual period in seconds
+pub(crate) const REWARD_PRECISION: u128 = 1_000_000_000_000_000_000; //fixed point 1.0 for compound interest
+
+mod access;
+mod admin;
+mod caps;
+mod events;
+mod fees;
 mod ft_calls;
+mod governance;
 mod internal;
+mod nft;
+mod pool;
+mod receipt;
+mod referral;
+mod stats;
+mod summary;
+mod tiers;
+mod voting;
+
+pub use crate::caps::RemainingCapacity;
+pub use crate::fees::{FeeConfig, Fees, Treasury};
+pub use crate::ft_calls::FTActionsReceiver;
+pub use crate::governance::{GovernanceConfig, Proposal, ProposalKind, ProposalStatus};
+pub use crate::pool::{PoolPosition, RewardPool};
+pub use crate::referral::ReferralConfig;
+pub use crate::stats::{LiabilityCheckpoint, StakingStats};
+pub use crate::summary::{StakeSummary, TokenSummary};
+pub use crate::tiers::Tier;
+pub use crate::voting::VotingCheckpoint;
 
 /* #[near_bindgen]
 #[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
@@ -33,7 +63,38 @@ pub struct Contract {
     pub staking_nonce: u128,
     pub claim_history: LookupMap<StakeId, ClaimHistory>,
     pub registered_members : LookupMap<AccountId, Vec<AccountId>>,
-    depositted_for_storage : LookupMap<AccountId,UnorderedSet<AccountId>>
+    depositted_for_storage : LookupMap<AccountId,UnorderedSet<AccountId>>,
+    stake_owners: LookupMap<StakeId, AccountId>,
+    receipt_token: FungibleToken,
+    receipt_ft_account_id: Option<AccountId>,
+    receipt_metadata: Option<FungibleTokenMetadata>,
+    unbonding: LookupMap<AccountId, Vec<Unbonding>>,
+    reward_pools: LookupMap<(AccountId, APYKey), RewardPool>,
+    pool_positions: LookupMap<(AccountId, AccountId, APYKey), PoolPosition>,
+    emission_pools: LookupMap<AccountId, RewardPool>,
+    voting_checkpoints: LookupMap<(AccountId, AccountId), Vec<VotingCheckpoint>>,
+    governance: Option<GovernanceConfig>,
+    proposal_nonce: u64,
+    proposals: LookupMap<u64, Proposal>,
+    proposal_votes: LookupMap<(u64, AccountId), bool>,
+    tiers: Vec<Tier>,
+    airdrop_tiers: LookupMap<AccountId, String>,
+    referral_configs: LookupMap<AccountId, ReferralConfig>,
+    referrers: LookupMap<AccountId, AccountId>,
+    referral_rewards: LookupMap<(AccountId, AccountId), U128>,
+    fee_configs: LookupMap<AccountId, FeeConfig>,
+    treasury: LookupMap<AccountId, Treasury>,
+    treasurer: AccountId,
+    plan_stats: LookupMap<(AccountId, APYKey), StakingStats>,
+    token_stats: LookupMap<AccountId, StakingStats>,
+    admins: UnorderedSet<AccountId>,
+    denylist: UnorderedSet<AccountId>,
+    plan_allowlists: LookupMap<(AccountId, APYKey), UnorderedSet<AccountId>>,
+    plan_checkpoints: LookupMap<(AccountId, APYKey), LiabilityCheckpoint>,
+    account_plan_stakes: LookupMap<(AccountId, AccountId, APYKey), u64>,
+    account_token_stakes: LookupMap<(AccountId, AccountId), u64>,
+    claimed_rewards: LookupMap<(AccountId, AccountId), U128>,
+    legacy_stakes: LookupMap<AccountId, Vec<StakeV1>>, //stakes made before the upgrade, see migrate_stakes
 }
 
 #[near_bindgen]
@@ -49,6 +110,91 @@ pub struct ContractV2 {
     depositted_for_storage : LookupMap<AccountId,UnorderedSet<AccountId>>
 }
 
+//state of the contract before the upgrade, read once by migrate
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct ContractV1 {
+    pub owner_id: AccountId,
+    pub approved_ft_token_ids: UnorderedSet<AccountId>,
+    pub approved_fts: LookupMap<AccountId, FTV1>,
+    staking_history: LookupMap<AccountId, Vec<StakeV1>>,
+    staking_nonce: u128,
+    claim_history: LookupMap<StakeId, ClaimHistory>,
+    registered_members : LookupMap<AccountId, Vec<AccountId>>,
+    depositted_for_storage : LookupMap<AccountId,UnorderedSet<AccountId>>
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct StakeV1 {
+    stake_id: StakeId,
+    ft_symbol: String,
+    ft_account_id: AccountId,
+    decimal: u8,
+    amount: U128,
+    duration: u64,
+    staked_at: u64,
+    staked_by: AccountId,
+    staking_plan: String,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct FTV1 {
+    pub account_id: AccountId,
+    pub symbol: String,
+    pub apy_against_duration: Option<HashMap<APYKey, APYV1>>,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct APYV1 {
+    pub apy_key: APYKey,
+    pub min_staking_amount: U128,
+    pub min_duration: u8,
+    pub interest_rate: u16,
+}
+
+impl From<StakeV1> for Stake {
+    fn from(stake: StakeV1) -> Self {
+        Self {
+            stake_id: stake.stake_id,
+            ft_symbol: stake.ft_symbol,
+            ft_account_id: stake.ft_account_id,
+            decimal: stake.decimal,
+            amount: stake.amount,
+            duration: stake.duration,
+            staked_at: stake.staked_at,
+            staked_by: stake.staked_by,
+            staking_plan: stake.staking_plan,
+            compounded_at: stake.staked_at,
+            unclaimed_reward: U128::from(0),
+            auto_renew: false,
+            beneficiary: None,
+            operator: None,
+            receipt_amount: U128::from(0),
+            reward_debt: U128::from(0),
+            weight: U128::from(0),
+            referrer: None,
+        }
+    }
+}
+
+impl From<FTV1> for FT {
+    fn from(ft: FTV1) -> Self {
+        Self {
+            account_id: ft.account_id,
+            symbol: ft.symbol,
+            apy_against_duration: ft.apy_against_duration.map(|apy_map| {
+                apy_map
+                    .into_iter()
+                    .map(|(key, apy)| {
+                        (key, APY::new(apy.apy_key, apy.min_staking_amount, apy.min_duration, apy.interest_rate))
+                    })
+                    .collect()
+            }),
+            max_total_staked: None,
+            decimals: None,
+        }
+    }
+}
+
 #[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
 #[serde(crate = "near_sdk::serde")]
 pub struct Stake {
@@ -61,6 +207,15 @@ pub struct Stake {
     staked_at: u64, //UNIX time : 1652793005
     staked_by: AccountId,
     staking_plan: String, //6months
+    compounded_at: u64, //last time rewards were added to the amount of an auto-compounding stake
+    unclaimed_reward: U128, //settled rewards waiting to be claimed
+    auto_renew: bool, //a matured stake is locked again for the same duration
+    beneficiary: Option<AccountId>, //receives rewards and principal instead of staked_by
+    operator: Option<AccountId>, //can claim and unstake on behalf of staked_by
+    receipt_amount: U128, //receipt tokens minted for this stake
+    reward_debt: U128, //emission rewards already accounted for this stake
+    weight: U128, //share of the stake in the emission pool
+    referrer: Option<AccountId>, //earns a share of the rewards claimed by this stake
 }
 #[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone,Debug)]
 #[serde(crate = "near_sdk::serde")]
@@ -68,6 +223,10 @@ pub struct FT {
     pub account_id: AccountId,
     pub symbol: String,
     pub apy_against_duration: Option<HashMap<APYKey, APY>>,
+    #[serde(default)]
+    pub max_total_staked: Option<U128>, //cap on the principal staked in all plans of the FT
+    #[serde(default)]
+    pub decimals: Option<u8>, //stakes of the FT count toward tiers once its decimals are known
 }
 
 #[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone,Debug)]
@@ -77,6 +236,60 @@ pub struct APY {
     pub min_staking_amount: U128,
     pub min_duration: u8,   //Ex 3 for 3 months
     pub interest_rate: u16, // Ex: 10% = 1000
+    #[serde(default)]
+    pub auto_compound: bool, //rewards are added to the staked amount instead of being claimed
+    #[serde(default)]
+    pub unbonding_period: Option<u64>, //in seconds, stakes can be unbonded any time instead of being locked
+    #[serde(default)]
+    pub plan_type: PlanType,
+    #[serde(default)]
+    pub boost: Option<u16>, //multiplier of the stake weight in emission pools, Ex: 2.5x = 250
+    #[serde(default)]
+    pub min_tier: Option<String>, //only members of this tier and above can stake in the plan
+    #[serde(default)]
+    pub max_total_staked: Option<U128>, //cap on the principal staked in the plan
+    #[serde(default)]
+    pub max_per_account: Option<U128>, //cap on the principal one account can stake in the plan
+    #[serde(default)]
+    pub opens_at: Option<u64>, //in seconds, deposits are only taken from this time
+    #[serde(default)]
+    pub closes_at: Option<u64>, //in seconds, deposits are only taken until this time
+    #[serde(default)]
+    pub matures_at: Option<u64>, //in seconds, every stake of the plan unlocks at this time
+    #[serde(default)]
+    pub allowlist_only: bool, //only accounts on the allowlist of the plan can stake in it
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone,Debug,PartialEq,Default)]
+#[serde(crate = "near_sdk::serde", rename_all = "snake_case")]
+pub enum PlanType {
+    #[default]
+    Fixed, //stakes are locked for a duration and earn the interest rate of the plan
+    Flexible, //no lock, any amount can be withdrawn and rewards accrue in a shared pool
+    Emission, //stakes are locked and share the emission rate set for the FT instead of earning interest
+}
+
+impl APY {
+    //a fixed plan without any of the optional settings
+    pub fn new(apy_key: APYKey, min_staking_amount: U128, min_duration: u8, interest_rate: u16) -> Self {
+        Self {
+            apy_key,
+            min_staking_amount,
+            min_duration,
+            interest_rate,
+            auto_compound: false,
+            unbonding_period: None,
+            plan_type: PlanType::Fixed,
+            boost: None,
+            min_tier: None,
+            max_total_staked: None,
+            max_per_account: None,
+            opens_at: None,
+            closes_at: None,
+            matures_at: None,
+            allowlist_only: false,
+        }
+    }
 }
 
 #[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone,Debug)]
@@ -88,6 +301,67 @@ pub struct StakeArgs {
     duration: u64, //duration in milliseconds Ex 30 days = 2629800
     staked_by: AccountId,
     staking_plan: String, //Ex 6months
+    #[serde(default)]
+    auto_renew: bool,
+    #[serde(default)]
+    beneficiary: Option<AccountId>,
+    #[serde(default)]
+    operator: Option<AccountId>,
+    #[serde(default)]
+    referrer: Option<AccountId>,
+}
+
+//a stake waiting for the unbonding period of its plan to pass
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Unbonding {
+    stake: Stake,
+    requested_at: u64,
+    unbonds_at: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone,Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct UnbondingTotal {
+    ft_account_id: AccountId,
+    unbonding: U128, //still in the unbonding period
+    withdrawable: U128, //unbonded and ready for withdraw_unbonded
+}
+
+//a stake paid out by a claim, recorded before the transfer and restored in the claim callback if it fails
+#[derive(Serialize, Deserialize, Clone,Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ClaimedStake {
+    stake_id: StakeId,
+    unclaimed_reward: U128,
+    reward: U128, //total paid for the stake with this claim
+    claim_history: Option<ClaimHistory>,
+    claim_count: u64,
+}
+
+//amounts taken out of a stake by ft_unstake, given back in the unstake callback if the transfer fails
+#[derive(Serialize, Deserialize, Clone,Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct UnstakedAmount {
+    amount: Option<U128>, //None when the whole stake is withdrawn
+    receipt_amount: U128, //receipts burned for the withdrawal
+}
+
+//tokens and rewards taken out of a flexible position, given back in the callback if the transfer fails
+#[derive(Serialize, Deserialize, Clone,Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PoolWithdrawal {
+    ft_account_id: AccountId,
+    staking_plan: String,
+    amount: U128,
+    reward: U128,
+}
+
+//ft_on_transfer messages that act on an existing stake instead of creating one
+#[derive(Serialize, Deserialize, Clone,Debug)]
+#[serde(crate = "near_sdk::serde", tag = "action", rename_all = "snake_case")]
+pub enum StakeAction {
+    TopUp { stake_id: StakeId },
 }
 
 #[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone,Debug)]
@@ -103,7 +377,33 @@ enum StorageKeys {
     AmountStaked,
     ClaimHistory,
     RegisteredMembers,
-    DeposittedForStorage
+    DeposittedForStorage,
+    StakeOwners,
+    ReceiptToken,
+    Unbonding,
+    RewardPools,
+    PoolPositions,
+    EmissionPools,
+    VotingCheckpoints,
+    Proposals,
+    ProposalVotes,
+    AirdropTiers,
+    ReferralConfigs,
+    Referrers,
+    ReferralRewards,
+    FeeConfigs,
+    Treasury,
+    PlanStats,
+    TokenStats,
+    Admins,
+    Denylist,
+    PlanAllowlists,
+    PlanAllowlist { plan_hash: Vec<u8> },
+    PlanCheckpoints,
+    AccountPlanStakes,
+    AccountTokenStakes,
+    ClaimedRewards,
+    StakingHistory,
 }
 
 #[near_bindgen]
@@ -111,16 +411,7 @@ impl Contract {
     #[init]
     pub fn new(owner_id: AccountId, approved_ft_token_ids: Vec<FT>, ft_apy: Vec<APY>) -> Self {
         assert!(!env::state_exists(), "Already initialized");
-        let mut this = Self {
-            owner_id: owner_id.into(),
-            approved_ft_token_ids: UnorderedSet::new(StorageKeys::ApproveFungibleTokens),
-            approved_fts: LookupMap::new(StorageKeys::ApproveFungibleTokens),
-            staking_history: LookupMap::new(StorageKeys::AmountStaked),
-            claim_history: LookupMap::new(StorageKeys::ClaimHistory),
-            staking_nonce: 0,
-            registered_members : LookupMap::new(StorageKeys::RegisteredMembers),
-            depositted_for_storage : LookupMap::new(StorageKeys::DeposittedForStorage)
-        };
+        let mut this = Contract::internal_new(owner_id);
 
         Contract::add_fts(
             approved_ft_token_ids,
@@ -133,8 +424,8 @@ impl Contract {
     }
 
     #[init(ignore_state)]
-    pub fn migrate()->ContractV2{
-        let prev: Contract = env::state_read().expect("ERR_NOT_INITIALIZED");
+    pub fn migrate()->Self{
+        let mut prev: ContractV1 = env::state_read().expect("ERR_NOT_INITIALIZED");
 
         assert_eq!(
             env::predecessor_account_id(),
@@ -142,21 +433,46 @@ impl Contract {
             "Only Owner can call this function"
         );
 
-        let this = ContractV2{
-            owner_id : prev.owner_id,
-            approved_ft_token_ids : prev.approved_ft_token_ids,
-            approved_fts : prev.approved_fts,
-            staking_history : prev.staking_history,
-            claim_history : prev.claim_history,
-            staking_nonce : prev.staking_nonce,
-            registered_members : LookupMap::new(StorageKeys::RegisteredMembers),
-            depositted_for_storage : LookupMap::new(StorageKeys::DeposittedForStorage)
-        };
+        let mut this = Contract::internal_new(prev.owner_id);
+        this.approved_ft_token_ids = prev.approved_ft_token_ids;
+        this.staking_nonce = prev.staking_nonce;
+        this.claim_history = prev.claim_history;
+        this.registered_members = prev.registered_members;
+        this.depositted_for_storage = prev.depositted_for_storage;
+        //plans are converted now, stakes are converted per account with migrate_stakes
+        this.legacy_stakes = prev.staking_history;
+        for ft_account_id in this.approved_ft_token_ids.to_vec() {
+            //both layouts share the storage prefix, the old entry goes first so it is never read as an FT
+            if let Some(ft) = prev.approved_fts.remove(&ft_account_id) {
+                this.approved_fts.insert(&ft_account_id, &FT::from(ft));
+            }
+        }
 
         this
 
     }
 
+    //converts the stakes made before the upgrade, the owner passes the stakers in batches
+    pub fn migrate_stakes(&mut self, account_ids: Vec<AccountId>) {
+        self.assert_owner();
+        for account_id in account_ids {
+            let legacy = match self.legacy_stakes.remove(&account_id) {
+                Some(legacy) => legacy,
+                None => continue,
+            };
+            let mut staking_history = self.staking_history.get(&account_id).unwrap_or_default();
+            for stake in legacy {
+                let mut stake = Stake::from(stake);
+                self.stake_owners.insert(&stake.stake_id, &account_id);
+                //legacy stakes were never counted in the totals and the emission pools
+                self.internal_join_pool(&mut stake);
+                self.internal_track_stake(&account_id, &stake.ft_account_id, &stake.staking_plan, true);
+                staking_history.push(stake);
+            }
+            self.internal_save_stakes(&account_id, &staking_history);
+        }
+    }
+
     pub fn get_staking_history(
         self,
         account_id: AccountId,
@@ -187,11 +503,7 @@ impl Contract {
 
     pub fn is_registered(&self,ft_contract_id:AccountId, account_id: AccountId)->bool{
         let registered_members = self.registered_members.get(&ft_contract_id);
-            if registered_members.unwrap().contains(&account_id){
-                true
-            }else{
-                false
-            }
+            registered_members.unwrap().contains(&account_id)
     }
 
     pub fn all_registered_memebers(&self, ft_contract_id:AccountId)->Vec<AccountId>{
@@ -210,11 +522,7 @@ impl Contract {
 
     pub fn has_depositted_for_storage(&self,account_id: AccountId,ft_contract_id:AccountId)->bool{
         if self.depositted_for_storage.get(&ft_contract_id).is_some(){
-            if self.depositted_for_storage.get(&ft_contract_id).unwrap().contains(&account_id){
-                true
-            }else{
-                false
-            }
+            self.depositted_for_storage.get(&ft_contract_id).unwrap().contains(&account_id)
         }else{
             false
         }
@@ -228,15 +536,132 @@ impl Contract {
     pub fn get_apy(&self, ft_contract_id:AccountId)->Option<FT>{
         self.approved_fts.get(&ft_contract_id)
     }
+
+    //reward accrued since the last claim, or since the last compounding for auto-compounding plans
+    pub fn pending_reward(&self, account_id: AccountId, stake_id: StakeId) -> U128 {
+        let stake = self.internal_get_stake(&account_id, stake_id);
+        U128::from(self.internal_pending_reward(&stake))
+    }
+
+    //flexible position with the rewards accrued until now
+    pub fn get_flexible_position(
+        &self,
+        account_id: AccountId,
+        ft_account_id: AccountId,
+        staking_plan: String,
+    ) -> PoolPosition {
+        let (pool, _) = self.internal_get_pool(&ft_account_id, &staking_plan);
+        let mut position = self.internal_get_position(&account_id, &ft_account_id, &staking_plan);
+        position.settle(&pool);
+        position
+    }
+
+    pub fn get_reward_pool(&self, ft_account_id: AccountId, staking_plan: String) -> RewardPool {
+        self.internal_get_pool(&ft_account_id, &staking_plan).0
+    }
+
+    pub fn get_emission_pool(&self, ft_account_id: AccountId) -> RewardPool {
+        self.internal_get_emission_pool(&ft_account_id)
+    }
+
+    //voting power of the locked stakes of an account in a token at a timestamp in seconds, now by default
+    pub fn voting_power(&self, account_id: AccountId, ft_account_id: AccountId, at_timestamp: Option<u64>) -> U128 {
+        let timestamp = at_timestamp.unwrap_or(env::block_timestamp() / 1000000000);
+        U128::from(self.internal_voting_power(&account_id, &ft_account_id, timestamp))
+    }
+
+    pub fn get_voting_checkpoints(&self, account_id: AccountId, ft_account_id: AccountId) -> Vec<VotingCheckpoint> {
+        self.voting_checkpoints.get(&(account_id, ft_account_id)).unwrap_or_default()
+    }
+
+    pub fn get_unbonding(&self, account_id: AccountId) -> Vec<Unbonding> {
+        self.unbonding.get(&account_id).unwrap_or_default()
+    }
+
+    pub fn get_unbonding_totals(&self, account_id: AccountId) -> Vec<UnbondingTotal> {
+        let current_time = env::block_timestamp() / 1000000000;
+        let mut totals: Vec<UnbondingTotal> = Vec::new();
+        for entry in self.unbonding.get(&account_id).unwrap_or_default() {
+            let amount = u128::from(entry.stake.amount) + u128::from(entry.stake.unclaimed_reward);
+            let index = match totals
+                .iter()
+                .position(|total| total.ft_account_id == entry.stake.ft_account_id)
+            {
+                Some(index) => index,
+                None => {
+                    totals.push(UnbondingTotal {
+                        ft_account_id: entry.stake.ft_account_id.clone(),
+                        unbonding: U128::from(0),
+                        withdrawable: U128::from(0),
+                    });
+                    totals.len() - 1
+                }
+            };
+            let total = &mut totals[index];
+            if current_time >= entry.unbonds_at {
+                total.withdrawable = U128::from(u128::from(total.withdrawable) + amount);
+            } else {
+                total.unbonding = U128::from(u128::from(total.unbonding) + amount);
+            }
+        }
+        totals
+    }
+}
+
+impl Contract {
+    fn internal_new(owner_id: AccountId) -> Self {
+        let treasurer = owner_id.clone();
+        Self {
+            owner_id,
+            approved_ft_token_ids: UnorderedSet::new(StorageKeys::ApproveFungibleTokens),
+            approved_fts: LookupMap::new(StorageKeys::ApproveFungibleTokens),
+            staking_history: LookupMap::new(StorageKeys::StakingHistory),
+            claim_history: LookupMap::new(StorageKeys::ClaimHistory),
+            staking_nonce: 0,
+            registered_members : LookupMap::new(StorageKeys::RegisteredMembers),
+            depositted_for_storage : LookupMap::new(StorageKeys::DeposittedForStorage),
+            stake_owners : LookupMap::new(StorageKeys::StakeOwners),
+            receipt_token : FungibleToken::new(StorageKeys::ReceiptToken),
+            receipt_ft_account_id : None,
+            receipt_metadata : None,
+            unbonding : LookupMap::new(StorageKeys::Unbonding),
+            reward_pools : LookupMap::new(StorageKeys::RewardPools),
+            pool_positions : LookupMap::new(StorageKeys::PoolPositions),
+            emission_pools : LookupMap::new(StorageKeys::EmissionPools),
+            voting_checkpoints : LookupMap::new(StorageKeys::VotingCheckpoints),
+            governance : None,
+            proposal_nonce : 0,
+            proposals : LookupMap::new(StorageKeys::Proposals),
+            proposal_votes : LookupMap::new(StorageKeys::ProposalVotes),
+            tiers : Vec::new(),
+            airdrop_tiers : LookupMap::new(StorageKeys::AirdropTiers),
+            referral_configs : LookupMap::new(StorageKeys::ReferralConfigs),
+            referrers : LookupMap::new(StorageKeys::Referrers),
+            referral_rewards : LookupMap::new(StorageKeys::ReferralRewards),
+            fee_configs : LookupMap::new(StorageKeys::FeeConfigs),
+            treasury : LookupMap::new(StorageKeys::Treasury),
+            treasurer,
+            plan_stats : LookupMap::new(StorageKeys::PlanStats),
+            token_stats : LookupMap::new(StorageKeys::TokenStats),
+            admins : UnorderedSet::new(StorageKeys::Admins),
+            denylist : UnorderedSet::new(StorageKeys::Denylist),
+            plan_allowlists : LookupMap::new(StorageKeys::PlanAllowlists),
+            plan_checkpoints : LookupMap::new(StorageKeys::PlanCheckpoints),
+            account_plan_stakes : LookupMap::new(StorageKeys::AccountPlanStakes),
+            account_token_stakes : LookupMap::new(StorageKeys::AccountTokenStakes),
+            claimed_rewards : LookupMap::new(StorageKeys::ClaimedRewards),
+            legacy_stakes : LookupMap::new(StorageKeys::AmountStaked),
+        }
+    }
 }
 
 #[cfg(test)]
 mod staking_tests {
 
     use super::*;
+    use crate::ft_calls::FTActionsReceiver;
     use near_sdk::test_utils::VMContextBuilder;
-    use near_sdk::{testing_env, VMContext,log,Gas};
-    use std::convert::TryInto;
+    use near_sdk::{testing_env, VMContext,log,PromiseOrValue,PromiseResult};
 
     const ALICE:&str = "alice.testnet";
 
@@ -249,49 +674,40 @@ mod staking_tests {
             .build()
     }
 
+    //context of a callback whose promise ended with the given result
+    fn set_promise_result(context: VMContext, result: PromiseResult) {
+        testing_env!(
+            context,
+            near_sdk::VMConfig::test(),
+            near_sdk::RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![result],
+        );
+    }
+
     #[test]
     fn test_contract_instantiation() {
-        let context = get_context(false);
-
-        let apy_dur:HashMap<APYKey, APY> = HashMap::new();
+        testing_env!(get_context(false));
 
-        let amount1:U128 = U128::from(500_000_000_000_000_0000_000_000_000);
-        let amount2:U128 = U128::from(1000_000_000_000_000_0000_000_000_000);
-        let amount3:U128 = U128::from(2000_000_000_000_000_0000_000_000_000);
+        let amount1:U128 = U128::from(5_000_000_000_000_000_000_000_000_000);
+        let amount2:U128 = U128::from(10_000_000_000_000_000_000_000_000_000);
+        let amount3:U128 = U128::from(20_000_000_000_000_000_000_000_000_000);
         let duration1 : String = "3months".to_string();
         let duration2 : String = "6months".to_string();
         let duration3 : String = "12months".to_string();
-        let apy1 : APY = APY{
-            apy_key : duration1,
-            min_staking_amount : amount1,
-            min_duration : 3,
-            interest_rate: 250,
-        };
-        let apy2 : APY = APY{
-            apy_key : duration2,
-            min_staking_amount : amount2,
-            min_duration : 6,
-            interest_rate: 500,
-        };
-        let apy3 : APY = APY{
-            apy_key : duration3,
-            min_staking_amount : amount3,
-            min_duration : 12,
-            interest_rate: 1000,
-        };
-        let mut fts : Vec<FT> = Vec::new();
-
+        let apy1 : APY = APY::new(duration1, amount1, 3, 250);
+        let apy2 : APY = APY::new(duration2, amount2, 6, 500);
+        let apy3 : APY = APY::new(duration3, amount3, 12, 1000);
         let ft = FT{
             account_id : AccountId::try_from(FT_CONTRACT.to_string()).unwrap(),
             symbol : "FT".to_string(),
-            apy_against_duration: None
+            apy_against_duration: None,
+            max_total_staked: None,
+            decimals: Some(24)
         };
-        fts.push(ft);
+        let fts : Vec<FT> = vec![ft];
 
-        let mut apys : Vec<APY> = Vec::new();
-        apys.push(apy1);
-        apys.push(apy2);
-        apys.push(apy3);
+        let apys : Vec<APY> = vec![apy1, apy2, apy3];
 
         let contract = Contract::new(AccountId::try_from(ALICE.to_string()).unwrap(),fts,apys);
 
@@ -304,48 +720,29 @@ mod staking_tests {
         testing_env!(context.clone());
 
 
-        let amount1:U128 = U128::from(500_000_000_000_000_0000_000_000_000);
-        let amount2:U128 = U128::from(1000_000_000_000_000_0000_000_000_000);
-        let amount3:U128 = U128::from(2000_000_000_000_000_0000_000_000_000);
+        let amount1:U128 = U128::from(5_000_000_000_000_000_000_000_000_000);
+        let amount2:U128 = U128::from(10_000_000_000_000_000_000_000_000_000);
+        let amount3:U128 = U128::from(20_000_000_000_000_000_000_000_000_000);
         let duration1 : String = "3months".to_string();
         let duration2 : String = "6months".to_string();
         let duration3 : String = "12months".to_string();
-        let apy1 : APY = APY{
-            apy_key : duration1,
-            min_staking_amount : amount1,
-            min_duration : 3,
-            interest_rate: 250,
-        };
-        let apy2 : APY = APY{
-            apy_key : duration2,
-            min_staking_amount : amount2,
-            min_duration : 6,
-            interest_rate: 500,
-        };
-        let apy3 : APY = APY{
-            apy_key : duration3,
-            min_staking_amount : amount3,
-            min_duration : 12,
-            interest_rate: 1000,
-        };
-        let mut fts : Vec<FT> = Vec::new();
-
+        let apy1 : APY = APY::new(duration1, amount1, 3, 250);
+        let apy2 : APY = APY::new(duration2, amount2, 6, 500);
+        let apy3 : APY = APY::new(duration3, amount3, 12, 1000);
         let ft = FT{
             account_id : AccountId::try_from(FT_CONTRACT.to_string()).unwrap(),
             symbol : "FT".to_string(),
-            apy_against_duration: None
+            apy_against_duration: None,
+            max_total_staked: None,
+            decimals: Some(24)
         };
-        fts.push(ft.clone());
+        let fts : Vec<FT> = vec![ft.clone()];
 
-        let mut apys : Vec<APY> = Vec::new();
-        apys.push(apy1);
-        apys.push(apy2);
-        apys.push(apy3);
+        let apys : Vec<APY> = vec![apy1, apy2, apy3];
 
         let mut contract = Contract::new(AccountId::try_from(ALICE.to_string()).unwrap(),fts,apys);
 
-        let mut registered_members : Vec<AccountId> = Vec::new();
-        registered_members.push(AccountId::try_from(ALICE.clone().to_string()).unwrap());
+        let registered_members : Vec<AccountId> = vec![AccountId::try_from(ALICE.to_string()).unwrap()];
         contract.registered_members.insert(&ft.account_id, &registered_members);
 
        // contract.all_registered_memebers(ft.account_id);
@@ -359,48 +756,30 @@ mod staking_tests {
         testing_env!(context.clone());
 
 
-        let amount1:U128 = U128::from(500_000_000_000_000_0000_000_000_000);
-        let amount2:U128 = U128::from(1000_000_000_000_000_0000_000_000_000);
-        let amount3:U128 = U128::from(2000_000_000_000_000_0000_000_000_000);
+        let amount1:U128 = U128::from(5_000_000_000_000_000_000_000_000_000);
+        let amount2:U128 = U128::from(10_000_000_000_000_000_000_000_000_000);
+        let amount3:U128 = U128::from(20_000_000_000_000_000_000_000_000_000);
         let duration1 : String = "3months".to_string();
         let duration2 : String = "6months".to_string();
         let duration3 : String = "12months".to_string();
-        let apy1 : APY = APY{
-            apy_key : duration1,
-            min_staking_amount : amount1,
-            min_duration : 3,
-            interest_rate: 250,
-        };
-        let apy2 : APY = APY{
-            apy_key : duration2,
-            min_staking_amount : amount2,
-            min_duration : 6,
-            interest_rate: 500,
-        };
-        let apy3 : APY = APY{
-            apy_key : duration3,
-            min_staking_amount : amount3,
-            min_duration : 12,
-            interest_rate: 1000,
-        };
-        let mut fts : Vec<FT> = Vec::new();
-
+        let apy1 : APY = APY::new(duration1, amount1, 3, 250);
+        let apy2 : APY = APY::new(duration2, amount2, 6, 500);
+        let apy3 : APY = APY::new(duration3, amount3, 12, 1000);
         let ft = FT{
             account_id : AccountId::try_from(FT_CONTRACT.to_string()).unwrap(),
             symbol : "FT".to_string(),
-            apy_against_duration: None
+            apy_against_duration: None,
+            max_total_staked: None,
+            decimals: Some(24)
         };
-        fts.push(ft.clone());
+        let fts : Vec<FT> = vec![ft.clone()];
 
-        let mut apys : Vec<APY> = Vec::new();
-        apys.push(apy1);
-        apys.push(apy2);
-        apys.push(apy3);
+        let apys : Vec<APY> = vec![apy1, apy2, apy3];
 
         let mut contract = Contract::new(AccountId::try_from(ALICE.to_string()).unwrap(),fts,apys);
 
         let mut members : UnorderedSet<AccountId> = UnorderedSet::new(b"s");
-        members.insert(&AccountId::try_from(ALICE.clone().to_string()).unwrap());
+        members.insert(&AccountId::try_from(ALICE.to_string()).unwrap());
         contract.depositted_for_storage.insert(&ft.account_id, &members);
 
     }
@@ -411,47 +790,19 @@ mod staking_tests {
         testing_env!(context.clone());
 
 
-        let amount1:U128 = U128::from(500_000_000_000_000_0000_000_000_000);
-        let amount2:U128 = U128::from(1000_000_000_000_000_0000_000_000_000);
-        let amount3:U128 = U128::from(2000_000_000_000_000_0000_000_000_000);
+        let amount1:U128 = U128::from(5_000_000_000_000_000_000_000_000_000);
         let duration1 : String = "3months".to_string();
-        let duration2 : String = "6months".to_string();
-        let duration3 : String = "12months".to_string();
-        let apy1 : APY = APY{
-            apy_key : duration1,
-            min_staking_amount : amount1,
-            min_duration : 3,
-            interest_rate: 250,
-        };
-        let apy2 : APY = APY{
-            apy_key : duration2,
-            min_staking_amount : amount2,
-            min_duration : 6,
-            interest_rate: 500,
-        };
-        let apy3 : APY = APY{
-            apy_key : duration3,
-            min_staking_amount : amount3,
-            min_duration : 12,
-            interest_rate: 1000,
-        };
-        let mut fts : Vec<FT> = Vec::new();
-
+        let apy1 : APY = APY::new(duration1, amount1, 3, 250);
         let ft = FT{
             account_id : AccountId::try_from(FT_CONTRACT.to_string()).unwrap(),
             symbol : "FT".to_string(),
-            apy_against_duration: None
+            apy_against_duration: None,
+            max_total_staked: None,
+            decimals: Some(24)
         };
-        fts.push(ft.clone());
-
-        let mut apys : Vec<APY> = Vec::new();
-        apys.push(apy1.clone());
-        apys.push(apy2.clone());
-        apys.push(apy3.clone());
 
         let mut contract = get_contract();
 
-        let mut staking_history : Vec<Stake> = Vec::new();
         let stake = Stake{
             stake_id : U128::from(1),
             ft_symbol : ft.symbol,
@@ -460,12 +811,20 @@ mod staking_tests {
             amount : U128::from(500000000000000000000000000),
             duration : 180,
             staked_at : env::block_timestamp()/1000000000,
-            staked_by : context.signer_account_id.clone().try_into().unwrap(),
-            staking_plan : apy1.clone().apy_key
+            staked_by : context.signer_account_id.clone(),
+            staking_plan : apy1.clone().apy_key,
+            compounded_at : env::block_timestamp()/1000000000,
+            unclaimed_reward : U128::from(0),
+            auto_renew : false,
+            beneficiary : None,
+            operator : None,
+            receipt_amount : U128::from(0),
+            reward_debt : U128::from(0),
+            weight : U128::from(0),
+            referrer : None
 
         };
-
-        staking_history.push(stake);
+        let staking_history : Vec<Stake> = vec![stake];
 
         contract.staking_history.insert(&ft.account_id, &staking_history);
     }
@@ -475,47 +834,52 @@ mod staking_tests {
         testing_env!(context.clone());
 
 
-        let amount1:U128 = U128::from(500_000_000_000_000_0000_000_000_000);
-        let amount2:U128 = U128::from(1000_000_000_000_000_0000_000_000_000);
-        let amount3:U128 = U128::from(2000_000_000_000_000_0000_000_000_000);
+        let amount1:U128 = U128::from(5_000_000_000_000_000_000_000_000_000);
+        let amount2:U128 = U128::from(10_000_000_000_000_000_000_000_000_000);
+        let amount3:U128 = U128::from(20_000_000_000_000_000_000_000_000_000);
         let duration1 : String = "3months".to_string();
         let duration2 : String = "6months".to_string();
         let duration3 : String = "12months".to_string();
-        let apy1 : APY = APY{
-            apy_key : duration1,
-            min_staking_amount : amount1,
-            min_duration : 3,
-            interest_rate: 250,
-        };
-        let apy2 : APY = APY{
-            apy_key : duration2,
-            min_staking_amount : amount2,
-            min_duration : 6,
-            interest_rate: 500,
-        };
-        let apy3 : APY = APY{
-            apy_key : duration3,
-            min_staking_amount : amount3,
-            min_duration : 12,
-            interest_rate: 1000,
-        };
-        let mut fts : Vec<FT> = Vec::new();
-
+        let apy1 : APY = APY::new(duration1, amount1, 3, 250);
+        let apy2 : APY = APY::new(duration2, amount2, 6, 500);
+        let apy3 : APY = APY::new(duration3, amount3, 12, 1000);
         let ft = FT{
             account_id : AccountId::try_from(FT_CONTRACT.to_string()).unwrap(),
             symbol : "FT".to_string(),
-            apy_against_duration: None
+            apy_against_duration: None,
+            max_total_staked: None,
+            decimals: Some(24)
         };
-        fts.push(ft.clone());
+        let fts : Vec<FT> = vec![ft.clone()];
 
-        let mut apys : Vec<APY> = Vec::new();
-        apys.push(apy1.clone());
-        apys.push(apy2.clone());
-        apys.push(apy3.clone());
+        let apys : Vec<APY> = vec![apy1.clone(), apy2.clone(), apy3.clone()];
 
-        let  contract = Contract::new(AccountId::try_from(ALICE.to_string()).unwrap(),fts,apys);
-        contract
+        Contract::new(AccountId::try_from(ALICE.to_string()).unwrap(),fts,apys)
+    }
+    //a fixed stake of the FT, locked for three minutes from the start of the chain
+    fn get_stake(stake_id: u128, staked_by: &AccountId, staking_plan: &str, amount: u128) -> Stake {
+        Stake{
+            stake_id : U128::from(stake_id),
+            ft_symbol : "FT".to_string(),
+            ft_account_id : AccountId::try_from(FT_CONTRACT.to_string()).unwrap(),
+            decimal : 24,
+            amount : U128::from(amount),
+            duration : 180,
+            staked_at : 0,
+            staked_by : staked_by.clone(),
+            staking_plan : staking_plan.to_string(),
+            compounded_at : 0,
+            unclaimed_reward : U128::from(0),
+            auto_renew : false,
+            beneficiary : None,
+            operator : None,
+            receipt_amount : U128::from(0),
+            reward_debt : U128::from(0),
+            weight : U128::from(0),
+            referrer : None
+        }
     }
+
     #[test]
     fn test_claim_history_insertion(){
         let mut contract = get_contract();
@@ -527,4 +891,1388 @@ mod staking_tests {
 
         contract.claim_history.insert(&U128::from(1), &claim_history);
     }
+
+    #[test]
+    fn test_compound_amount(){
+        //0.1% over 100 minutes, a thousandth of a percent each minute
+        let mut apy = APY::new("100minutes".to_string(), U128::from(0), 100, 1);
+        apy.auto_compound = true;
+
+        let amount: u128 = 1_000_000_000_000_000_000_000_000;
+        assert_eq!(Contract::compound_amount(amount, &apy, 0), Some(amount));
+        //a single minute pays the same with or without compounding
+        assert_eq!(Contract::simple_reward(amount, &apy, 1), 10_000_000_000_000_000_000);
+        assert_eq!(Contract::compound_amount(amount, &apy, 1), Some(amount + Contract::simple_reward(amount, &apy, 1)));
+        //after that compounding pays more
+        assert!(Contract::compound_amount(amount, &apy, 100).unwrap() > amount + Contract::simple_reward(amount, &apy, 100));
+    }
+
+    #[test]
+    fn test_compound_amount_long_horizon(){
+        let mut apy = APY::new("100minutes".to_string(), U128::from(0), 100, 1);
+        apy.auto_compound = true;
+        let amount: u128 = 1_000_000_000_000_000_000_000_000;
+        //compounding the minute rate for 100000 minutes is close to e
+        let compounded = Contract::compound_amount(amount, &apy, 100_000).unwrap();
+        assert!(compounded > 2_718_260_000_000_000_000_000_000 && compounded < 2_718_290_000_000_000_000_000_000);
+
+        //amounts that cannot be represented are an error instead of a number that can never be paid
+        apy.interest_rate = u16::MAX;
+        apy.min_duration = 1;
+        assert_eq!(Contract::compound_amount(amount, &apy, 100), None);
+    }
+
+    #[test]
+    fn test_unlocks_at_with_auto_renew(){
+        let mut stake = Stake{
+            stake_id : U128::from(1),
+            ft_symbol : "FT".to_string(),
+            ft_account_id : AccountId::try_from(FT_CONTRACT.to_string()).unwrap(),
+            decimal : 24,
+            amount : U128::from(500),
+            duration : 180,
+            staked_at : 1000,
+            staked_by : AccountId::try_from(ALICE.to_string()).unwrap(),
+            staking_plan : "3months".to_string(),
+            compounded_at : 1000,
+            unclaimed_reward : U128::from(0),
+            auto_renew : false,
+            beneficiary : None,
+            operator : None,
+            receipt_amount : U128::from(0),
+            reward_debt : U128::from(0),
+            weight : U128::from(0),
+            referrer : None
+        };
+
+        assert_eq!(Contract::unlocks_at(&stake, 1500), 1180);
+
+        stake.auto_renew = true;
+        assert_eq!(Contract::unlocks_at(&stake, 1100), 1180);
+        assert_eq!(Contract::unlocks_at(&stake, 1180), 1360);
+        assert_eq!(Contract::unlocks_at(&stake, 1500), 1540);
+    }
+
+    #[test]
+    fn test_flexible_reward_pool(){
+        let mut pool = RewardPool::new(0);
+        let mut position = PoolPosition::new();
+        position.set_amount(1000, &pool);
+
+        //10% a year
+        pool.update(1000, 31_536_000);
+        position.settle(&pool);
+        assert_eq!(u128::from(position.pending_reward), 100);
+
+        position.set_amount(2000, &pool);
+        pool.update(1000, 31_536_000 * 2);
+        position.settle(&pool);
+        assert_eq!(u128::from(position.pending_reward), 300);
+    }
+
+    #[test]
+    fn test_emission_reward_pool(){
+        let mut pool = RewardPool::new(0);
+        pool.emission_per_second = U128::from(10);
+        pool.total_staked = U128::from(1000);
+
+        //only one stake, it gets the whole emission
+        pool.update(0, 100);
+        assert_eq!(pool.accumulated(1000), 1000);
+
+        let second_debt = pool.accumulated(3000);
+        pool.total_staked = U128::from(4000);
+        pool.update(0, 200);
+        assert_eq!(pool.accumulated(1000), 1250);
+        assert_eq!(pool.accumulated(3000) - second_debt, 750);
+    }
+
+    #[test]
+    fn test_voting_power_decay(){
+        let account_id = AccountId::try_from(ALICE.to_string()).unwrap();
+        let mut stake = get_stake(1, &account_id, "12months", 1000);
+        stake.duration = 31_536_000;
+        let checkpoint = VotingCheckpoint::from_stakes(0, [stake].iter());
+        assert_eq!(checkpoint.power_at(0), 1000);
+        assert_eq!(checkpoint.power_at(31_536_000 / 4), 750);
+        assert_eq!(checkpoint.power_at(31_536_000), 0);
+        assert_eq!(checkpoint.power_at(31_536_000 * 2), 0);
+    }
+
+    #[test]
+    fn test_voting_power_after_lock_end(){
+        let account_id = AccountId::try_from(ALICE.to_string()).unwrap();
+        let mut long = get_stake(1, &account_id, "12months", 1000);
+        long.duration = 31_536_000;
+        let mut short = get_stake(2, &account_id, "12months", 1000);
+        short.duration = 31_536_000 / 2;
+        let checkpoint = VotingCheckpoint::from_stakes(0, [long, short].iter());
+        assert_eq!(checkpoint.power_at(0), 1500);
+        //the short lock stops losing power when it ends, only the long one runs down after that
+        assert_eq!(checkpoint.power_at(31_536_000 / 2), 500);
+        assert_eq!(checkpoint.power_at(31_536_000 / 4 * 3), 250);
+        assert_eq!(checkpoint.power_at(31_536_000), 0);
+    }
+
+    #[test]
+    fn test_set_apy(){
+        let mut contract = get_contract();
+        let ft_account_id = AccountId::try_from(FT_CONTRACT.to_string()).unwrap();
+        let mut apy = contract.internal_get_apy(&ft_account_id, "6months");
+        apy.interest_rate = 750;
+        contract.internal_set_apy(&ft_account_id, apy);
+        assert_eq!(contract.internal_get_apy(&ft_account_id, "6months").interest_rate, 750);
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot change the type of a staking plan")]
+    fn test_set_apy_plan_type(){
+        let mut contract = get_contract();
+        let ft_account_id = AccountId::try_from(FT_CONTRACT.to_string()).unwrap();
+        let mut apy = contract.internal_get_apy(&ft_account_id, "6months");
+        apy.plan_type = PlanType::Flexible;
+        contract.internal_set_apy(&ft_account_id, apy);
+    }
+
+    #[test]
+    fn test_get_tier(){
+        let mut contract = get_contract();
+        contract.tiers = vec![
+            Tier{ name : "Bronze".to_string(), min_staked : U128::from(100) },
+            Tier{ name : "Silver".to_string(), min_staked : U128::from(1000) },
+            Tier{ name : "Gold".to_string(), min_staked : U128::from(10000) },
+        ];
+        let account_id = AccountId::try_from(ALICE.to_string()).unwrap();
+        assert!(contract.get_tier(account_id.clone()).is_none());
+
+        let stake = Stake{
+            stake_id : U128::from(1),
+            ft_symbol : "FT".to_string(),
+            ft_account_id : AccountId::try_from(FT_CONTRACT.to_string()).unwrap(),
+            decimal : 24,
+            amount : U128::from(5_000_000_000_000_000_000_000_000_000),
+            duration : 180,
+            staked_at : 0,
+            staked_by : account_id.clone(),
+            staking_plan : "3months".to_string(),
+            compounded_at : 0,
+            unclaimed_reward : U128::from(0),
+            auto_renew : false,
+            beneficiary : None,
+            operator : None,
+            receipt_amount : U128::from(0),
+            reward_debt : U128::from(0),
+            weight : U128::from(0),
+            referrer : None
+        };
+        contract.staking_history.insert(&account_id, &vec![stake]);
+        assert_eq!(contract.get_tier(account_id.clone()).unwrap().name, "Silver");
+    }
+
+    #[test]
+    #[should_panic(expected = "Referral cycles are not allowed")]
+    fn test_referral_cycle(){
+        let mut contract = get_contract();
+        let ft_account_id = AccountId::try_from(FT_CONTRACT.to_string()).unwrap();
+        let alice = AccountId::try_from(ALICE.to_string()).unwrap();
+        let bob = AccountId::try_from("bob.testnet".to_string()).unwrap();
+        let carol = AccountId::try_from("carol.testnet".to_string()).unwrap();
+
+        contract.internal_set_referrer(&bob, &ft_account_id, Some(alice.clone()));
+        contract.internal_set_referrer(&carol, &ft_account_id, Some(bob.clone()));
+        contract.internal_set_referrer(&alice, &ft_account_id, Some(carol));
+    }
+
+    #[test]
+    fn test_referral_flat_bonus(){
+        let mut contract = get_contract();
+        let ft_account_id = AccountId::try_from(FT_CONTRACT.to_string()).unwrap();
+        let alice = AccountId::try_from(ALICE.to_string()).unwrap();
+        let bob = AccountId::try_from("bob.testnet".to_string()).unwrap();
+        contract.referral_configs.insert(&ft_account_id, &ReferralConfig{
+            reward_bps : 500,
+            flat_bonus : U128::from(100),
+        });
+
+        contract.internal_set_referrer(&bob, &ft_account_id, Some(alice.clone()));
+        //later stakes keep the referrer without paying the bonus again
+        let referrer = contract.internal_set_referrer(&bob, &ft_account_id, None);
+        assert_eq!(referrer, Some(alice.clone()));
+        assert_eq!(u128::from(contract.get_referral_rewards(alice.clone(), ft_account_id.clone())), 100);
+
+        //an account that already staked is referred without the bonus
+        let carol = AccountId::try_from("carol.testnet".to_string()).unwrap();
+        contract.staking_history.insert(&carol, &vec![get_stake(1, &carol, "3months", 1000)]);
+        contract.internal_set_referrer(&carol, &ft_account_id, Some(alice.clone()));
+        assert_eq!(contract.get_referrer(carol), Some(alice.clone()));
+        assert_eq!(u128::from(contract.get_referral_rewards(alice, ft_account_id)), 100);
+    }
+
+    #[test]
+    fn test_fees(){
+        let config = FeeConfig{
+            performance_fee_bps : 1000,
+            deposit_fee_bps : 0,
+            withdrawal_fee_bps : 50,
+            early_unstake_penalty_bps : 500,
+        };
+        let ft_account_id = AccountId::try_from(FT_CONTRACT.to_string()).unwrap();
+        let mut fees = Fees::new(&ft_account_id);
+        fees.on_withdrawal(10000, &config, false);
+        fees.on_rewards(300, &config);
+        assert_eq!(fees.total(), 80);
+
+        fees.on_withdrawal(10000, &config, true);
+        assert_eq!(u128::from(fees.penalty), 500);
+        assert_eq!(fees.total(), 630);
+    }
+
+    #[test]
+    #[should_panic(expected = "Withdrawal fee and early unstake penalty cannot be more than 100% together")]
+    fn test_fee_config_over_principal(){
+        let mut contract = get_contract();
+        testing_env!(VMContextBuilder::new().predecessor_account_id(AccountId::try_from(ALICE.to_string()).unwrap()).build());
+        let ft_account_id = AccountId::try_from(FT_CONTRACT.to_string()).unwrap();
+        contract.set_fee_config(ft_account_id, FeeConfig{
+            performance_fee_bps : 1000,
+            deposit_fee_bps : 0,
+            withdrawal_fee_bps : 6000,
+            early_unstake_penalty_bps : 5000,
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot stake tokens of another FT")]
+    fn test_stake_other_ft(){
+        let mut contract = get_contract();
+        let account_id = AccountId::try_from(ALICE.to_string()).unwrap();
+        let other_ft = AccountId::try_from("other.testnet".to_string()).unwrap();
+        contract.approved_ft_token_ids.insert(&other_ft);
+        testing_env!(VMContextBuilder::new().predecessor_account_id(other_ft).build());
+
+        let msg = format!(
+            r#"{{"ft_symbol":"FT","ft_account_id":"{}","decimal":24,"duration":180,"staked_by":"{}","staking_plan":"3months"}}"#,
+            FT_CONTRACT, ALICE
+        );
+        contract.ft_on_transfer(account_id, U128::from(1_000_000_000_000_000_000_000_000_000u128), msg);
+    }
+
+    #[test]
+    fn test_staking_caps(){
+        let mut contract = get_contract();
+        let ft_account_id = AccountId::try_from(FT_CONTRACT.to_string()).unwrap();
+        let account_id = AccountId::try_from(ALICE.to_string()).unwrap();
+        let mut apy = contract.internal_get_apy(&ft_account_id, "3months");
+        apy.max_total_staked = Some(U128::from(1000));
+        contract.internal_set_apy(&ft_account_id, apy);
+
+        contract.internal_add_staked(&ft_account_id, "3months", 700);
+        assert_eq!(contract.internal_accept_deposit(&account_id, &ft_account_id, "3months", 500), 300);
+        assert_eq!(u128::from(contract.get_staked_total(ft_account_id.clone(), None)), 700);
+
+        contract.internal_remove_staked(&ft_account_id, "3months", 200);
+        let capacity = contract.get_remaining_capacity(ft_account_id, "3months".to_string(), None);
+        assert_eq!(u128::from(capacity.plan.unwrap()), 500);
+        assert!(capacity.token.is_none());
+    }
+
+    #[test]
+    fn test_plan_window(){
+        let contract = get_contract();
+        let ft_account_id = AccountId::try_from(FT_CONTRACT.to_string()).unwrap();
+        let mut apy = contract.internal_get_apy(&ft_account_id, "3months");
+        apy.opens_at = Some(100);
+        apy.closes_at = Some(200);
+        apy.matures_at = Some(500);
+        Contract::assert_plan_open(&apy, 150);
+        //every stake of the plan unlocks at the maturity date
+        assert_eq!(Contract::plan_duration(&apy, 150, 180), 350);
+        assert_eq!(Contract::plan_duration(&apy, 199, 180), 301);
+    }
+
+    #[test]
+    #[should_panic(expected = "Staking plan is closed")]
+    fn test_plan_closed(){
+        let contract = get_contract();
+        let ft_account_id = AccountId::try_from(FT_CONTRACT.to_string()).unwrap();
+        let mut apy = contract.internal_get_apy(&ft_account_id, "3months");
+        apy.closes_at = Some(200);
+        Contract::assert_plan_open(&apy, 200);
+    }
+
+    #[test]
+    fn test_plan_allowlist(){
+        let mut contract = get_contract();
+        let ft_account_id = AccountId::try_from(FT_CONTRACT.to_string()).unwrap();
+        let account_id = AccountId::try_from(ALICE.to_string()).unwrap();
+        let mut apy = contract.internal_get_apy(&ft_account_id, "3months");
+        apy.allowlist_only = true;
+        contract.internal_set_apy(&ft_account_id, apy);
+        assert!(!contract.is_allowed(account_id.clone(), ft_account_id.clone(), "3months".to_string()));
+
+        testing_env!(VMContextBuilder::new().predecessor_account_id(account_id.clone()).build());
+        contract.allow_accounts(ft_account_id.clone(), "3months".to_string(), vec![account_id.clone()]);
+        assert!(contract.is_allowed(account_id.clone(), ft_account_id.clone(), "3months".to_string()));
+
+        contract.deny_accounts(vec![account_id.clone()]);
+        assert!(!contract.is_allowed(account_id.clone(), ft_account_id.clone(), "3months".to_string()));
+        assert_eq!(contract.get_denylist(None, None), vec![account_id.clone()]);
+
+        contract.undeny_accounts(vec![account_id.clone()]);
+        contract.disallow_accounts(ft_account_id.clone(), "3months".to_string(), vec![account_id.clone()]);
+        assert!(!contract.is_allowed(account_id, ft_account_id, "3months".to_string()));
+    }
+
+    #[test]
+    fn test_admin_lists(){
+        let mut contract = get_contract();
+        let alice = AccountId::try_from(ALICE.to_string()).unwrap();
+        let bob = AccountId::try_from("bob.testnet".to_string()).unwrap();
+        testing_env!(VMContextBuilder::new().predecessor_account_id(alice.clone()).build());
+        contract.add_admin(bob.clone());
+
+        //admins manage the lists without being the owner
+        testing_env!(VMContextBuilder::new().predecessor_account_id(bob.clone()).build());
+        contract.deny_accounts(vec![alice.clone()]);
+        assert_eq!(contract.get_denylist(None, None), vec![alice]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Admin's method")]
+    fn test_deny_accounts_not_admin(){
+        let mut contract = get_contract();
+        let bob = AccountId::try_from("bob.testnet".to_string()).unwrap();
+        testing_env!(VMContextBuilder::new().predecessor_account_id(bob.clone()).build());
+        contract.deny_accounts(vec![bob]);
+    }
+
+    #[test]
+    fn test_denied_keeps_principal(){
+        let mut contract = get_contract();
+        let ft_account_id = AccountId::try_from(FT_CONTRACT.to_string()).unwrap();
+        let account_id = AccountId::try_from(ALICE.to_string()).unwrap();
+        let mut apy = contract.internal_get_apy(&ft_account_id, "3months");
+        apy.auto_compound = true;
+        contract.internal_set_apy(&ft_account_id, apy);
+        let amount: u128 = 10_000_000_000_000_000_000_000_000_000;
+        let mut stake = get_stake(1, &account_id, "3months", amount);
+        contract.internal_add_staked(&ft_account_id, "3months", amount);
+        contract.denylist.insert(&account_id);
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(account_id.clone())
+            .block_timestamp(3600 * 1_000_000_000)
+            .build());
+
+        //what compounded while denied is not added to the stake
+        contract.internal_leave_pool(&mut stake);
+        assert_eq!(u128::from(stake.amount), amount);
+        assert_eq!(stake.compounded_at, 3600);
+        let stats = contract.get_plan_stats(ft_account_id, "3months".to_string());
+        assert_eq!(u128::from(stats.rewards_paid), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Account is denied")]
+    fn test_denied_transfer_stake(){
+        let mut contract = get_contract();
+        let alice = AccountId::try_from(ALICE.to_string()).unwrap();
+        let bob = AccountId::try_from("bob.testnet".to_string()).unwrap();
+        contract.staking_history.insert(&alice, &vec![get_stake(1, &alice, "3months", 1000)]);
+        contract.denylist.insert(&alice);
+        contract.internal_transfer_stake(&alice, &bob, U128::from(1));
+    }
+
+    #[test]
+    fn test_staking_stats(){
+        let mut contract = get_contract();
+        let ft_account_id = AccountId::try_from(FT_CONTRACT.to_string()).unwrap();
+        let alice = AccountId::try_from(ALICE.to_string()).unwrap();
+        let bob = AccountId::try_from("bob.testnet".to_string()).unwrap();
+        contract.internal_track_stake(&alice, &ft_account_id, "3months", true);
+        contract.internal_track_stake(&alice, &ft_account_id, "3months", true);
+        contract.internal_track_stake(&bob, &ft_account_id, "6months", true);
+        contract.internal_add_staked(&ft_account_id, "3months", 700);
+
+        let stats = contract.get_token_stats(ft_account_id.clone());
+        assert_eq!(u128::from(stats.total_staked), 700);
+        assert_eq!(stats.active_stakes, 3);
+        assert_eq!(stats.unique_stakers, 2);
+
+        //alice stays a staker until her last stake is gone
+        contract.internal_track_stake(&alice, &ft_account_id, "3months", false);
+        let stats = contract.get_plan_stats(ft_account_id.clone(), "3months".to_string());
+        assert_eq!((stats.active_stakes, stats.unique_stakers), (1, 1));
+        contract.internal_track_stake(&alice, &ft_account_id, "3months", false);
+        let stats = contract.get_plan_stats(ft_account_id.clone(), "3months".to_string());
+        assert_eq!((stats.active_stakes, stats.unique_stakers), (0, 0));
+        assert_eq!(contract.get_token_stats(ft_account_id.clone()).unique_stakers, 1);
+
+        contract.internal_release_rewards(&ft_account_id, "6months", 50, true);
+        contract.internal_release_rewards(&ft_account_id, "6months", 20, false);
+        let stats = contract.get_token_stats(ft_account_id);
+        assert_eq!(u128::from(stats.rewards_paid), 50);
+        assert_eq!(u128::from(stats.pending_liabilities), 0);
+    }
+
+    #[test]
+    fn test_account_summary(){
+        let mut contract = get_contract();
+        let ft_account_id = AccountId::try_from(FT_CONTRACT.to_string()).unwrap();
+        let account_id = AccountId::try_from(ALICE.to_string()).unwrap();
+        assert!(contract.get_account_summary(account_id.clone()).is_empty());
+
+        let stake = Stake{
+            stake_id : U128::from(1),
+            ft_symbol : "FT".to_string(),
+            ft_account_id : ft_account_id.clone(),
+            decimal : 24,
+            amount : U128::from(1000),
+            duration : 180,
+            staked_at : 0,
+            staked_by : account_id.clone(),
+            staking_plan : "3months".to_string(),
+            compounded_at : 0,
+            unclaimed_reward : U128::from(40),
+            auto_renew : false,
+            beneficiary : None,
+            operator : None,
+            receipt_amount : U128::from(0),
+            reward_debt : U128::from(0),
+            weight : U128::from(0),
+            referrer : None
+        };
+        contract.staking_history.insert(&account_id, &vec![stake]);
+        contract.internal_add_claimed(&account_id, &ft_account_id, 50);
+
+        let summary = contract.get_account_summary(account_id.clone());
+        assert_eq!(summary.len(), 1);
+        assert_eq!(u128::from(summary[0].staked), 1000);
+        assert_eq!(u128::from(summary[0].claimable), 40);
+        assert_eq!(u128::from(summary[0].claimed), 50);
+        assert_eq!(summary[0].locked_until, Some(180));
+        assert_eq!(summary[0].next_unlock_at, Some(180));
+        assert_eq!(u128::from(summary[0].stakes[0].pending_reward), 40);
+
+        //unbonding principal is still staked
+        let unbonding = get_stake(2, &account_id, "3months", 500);
+        contract.unbonding.insert(&account_id, &vec![Unbonding{
+            stake : unbonding,
+            requested_at : 0,
+            unbonds_at : 600,
+        }]);
+        let summary = contract.get_account_summary(account_id.clone());
+        assert_eq!(u128::from(summary[0].staked), 1500);
+        assert_eq!(u128::from(summary[0].claimable), 40);
+        assert_eq!(summary[0].stakes.len(), 1);
+
+        //denied accounts cannot claim
+        contract.denylist.insert(&account_id);
+        let summary = contract.get_account_summary(account_id);
+        assert_eq!(u128::from(summary[0].claimable), 0);
+    }
+
+    #[test]
+    fn test_migrate(){
+        let alice = AccountId::try_from(ALICE.to_string()).unwrap();
+        let ft_account_id = AccountId::try_from(FT_CONTRACT.to_string()).unwrap();
+        testing_env!(VMContextBuilder::new().predecessor_account_id(alice.clone()).build());
+
+        let mut apys = HashMap::new();
+        apys.insert("3months".to_string(), APYV1{
+            apy_key : "3months".to_string(),
+            min_staking_amount : U128::from(100),
+            min_duration : 3,
+            interest_rate : 250,
+        });
+        let mut prev = ContractV1{
+            owner_id : alice.clone(),
+            approved_ft_token_ids : UnorderedSet::new(StorageKeys::ApproveFungibleTokens),
+            approved_fts : LookupMap::new(StorageKeys::ApproveFungibleTokens),
+            staking_history : LookupMap::new(StorageKeys::AmountStaked),
+            staking_nonce : 1,
+            claim_history : LookupMap::new(StorageKeys::ClaimHistory),
+            registered_members : LookupMap::new(StorageKeys::RegisteredMembers),
+            depositted_for_storage : LookupMap::new(StorageKeys::DeposittedForStorage),
+        };
+        prev.approved_ft_token_ids.insert(&ft_account_id);
+        prev.approved_fts.insert(&ft_account_id, &FTV1{
+            account_id : ft_account_id.clone(),
+            symbol : "FT".to_string(),
+            apy_against_duration : Some(apys),
+        });
+        prev.staking_history.insert(&alice, &vec![StakeV1{
+            stake_id : U128::from(1),
+            ft_symbol : "FT".to_string(),
+            ft_account_id : ft_account_id.clone(),
+            decimal : 24,
+            amount : U128::from(1000),
+            duration : 180,
+            staked_at : 60,
+            staked_by : alice.clone(),
+            staking_plan : "3months".to_string(),
+        }]);
+        env::state_write(&prev);
+
+        let mut contract = Contract::migrate();
+        assert_eq!(contract.staking_nonce, 1);
+        assert_eq!(contract.internal_get_apy(&ft_account_id, "3months").interest_rate, 250);
+
+        //stakes are only visible once their account is migrated, and only once
+        contract.migrate_stakes(vec![alice.clone()]);
+        contract.migrate_stakes(vec![alice.clone()]);
+        let stakes = contract.staking_history.get(&alice).unwrap();
+        assert_eq!(stakes.len(), 1);
+        assert_eq!(stakes[0].compounded_at, 60);
+        assert_eq!(contract.stake_owners.get(&U128::from(1)), Some(alice));
+        //migrated stakes count in the totals of their plan
+        let stats = contract.get_plan_stats(ft_account_id, "3months".to_string());
+        assert_eq!(u128::from(stats.total_staked), 1000);
+        assert_eq!(stats.active_stakes, 1);
+        assert_eq!(stats.unique_stakers, 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "Turn off auto renewal before restaking")]
+    fn test_restake_auto_renew(){
+        let mut contract = get_contract();
+        let account_id = AccountId::try_from(ALICE.to_string()).unwrap();
+        let stake = Stake{
+            stake_id : U128::from(1),
+            ft_symbol : "FT".to_string(),
+            ft_account_id : AccountId::try_from(FT_CONTRACT.to_string()).unwrap(),
+            decimal : 24,
+            amount : U128::from(1000),
+            duration : 180,
+            staked_at : 0,
+            staked_by : account_id.clone(),
+            staking_plan : "3months".to_string(),
+            compounded_at : 0,
+            unclaimed_reward : U128::from(0),
+            auto_renew : true,
+            beneficiary : None,
+            operator : None,
+            receipt_amount : U128::from(0),
+            reward_debt : U128::from(0),
+            weight : U128::from(0),
+            referrer : None
+        };
+        contract.staking_history.insert(&account_id, &vec![stake]);
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(account_id)
+            .block_timestamp(1000 * 1_000_000_000)
+            .build());
+        contract.restake(U128::from(1), "6months".to_string(), 360, false);
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot leave less than")]
+    fn test_partial_unstake_below_minimum(){
+        let mut contract = get_contract();
+        let account_id = AccountId::try_from(ALICE.to_string()).unwrap();
+        let stake = Stake{
+            stake_id : U128::from(1),
+            ft_symbol : "FT".to_string(),
+            ft_account_id : AccountId::try_from(FT_CONTRACT.to_string()).unwrap(),
+            decimal : 24,
+            amount : U128::from(10_000_000_000_000_000_000_000_000_000),
+            duration : 180,
+            staked_at : 0,
+            staked_by : account_id.clone(),
+            staking_plan : "3months".to_string(),
+            compounded_at : 0,
+            unclaimed_reward : U128::from(0),
+            auto_renew : false,
+            beneficiary : None,
+            operator : None,
+            receipt_amount : U128::from(0),
+            reward_debt : U128::from(0),
+            weight : U128::from(0),
+            referrer : None
+        };
+        contract.staking_history.insert(&account_id, &vec![stake]);
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(account_id)
+            .block_timestamp(1000 * 1_000_000_000)
+            .build());
+        //the 3months plan needs at least 500 tokens staked
+        contract.ft_unstake(U128::from(1), Some(U128::from(6_000_000_000_000_000_000_000_000_000)));
+    }
+
+    #[test]
+    fn test_extend_lock_from_auto_compound(){
+        let mut contract = get_contract();
+        let ft_account_id = AccountId::try_from(FT_CONTRACT.to_string()).unwrap();
+        let account_id = AccountId::try_from(ALICE.to_string()).unwrap();
+        let mut apy = contract.internal_get_apy(&ft_account_id, "3months");
+        apy.auto_compound = true;
+        contract.internal_set_apy(&ft_account_id, apy);
+        let amount: u128 = 10_000_000_000_000_000_000_000_000_000;
+        let stake = Stake{
+            stake_id : U128::from(1),
+            ft_symbol : "FT".to_string(),
+            ft_account_id : ft_account_id.clone(),
+            decimal : 24,
+            amount : U128::from(amount),
+            duration : 180,
+            staked_at : 0,
+            staked_by : account_id.clone(),
+            staking_plan : "3months".to_string(),
+            compounded_at : 0,
+            unclaimed_reward : U128::from(0),
+            auto_renew : false,
+            beneficiary : None,
+            operator : None,
+            receipt_amount : U128::from(0),
+            reward_debt : U128::from(0),
+            weight : U128::from(0),
+            referrer : None
+        };
+        contract.staking_history.insert(&account_id, &vec![stake]);
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(account_id.clone())
+            .block_timestamp(3600 * 1_000_000_000)
+            .build());
+        contract.extend_lock(U128::from(1), "6months".to_string(), None);
+
+        //the hour was compounded into the stake and is not paid again by the fixed plan
+        let stake = contract.internal_get_stake(&account_id, U128::from(1));
+        assert!(u128::from(stake.amount) > amount);
+        assert_eq!(contract.internal_pending_reward(&stake), 0);
+    }
+
+    #[test]
+    fn test_top_up(){
+        let mut contract = get_contract();
+        let ft_account_id = AccountId::try_from(FT_CONTRACT.to_string()).unwrap();
+        let account_id = AccountId::try_from(ALICE.to_string()).unwrap();
+        let amount: u128 = 10_000_000_000_000_000_000_000_000_000;
+        let stake = get_stake(1, &account_id, "3months", amount);
+        contract.staking_history.insert(&account_id, &vec![stake]);
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(ft_account_id)
+            .block_timestamp(100 * 1_000_000_000)
+            .build());
+
+        let msg = r#"{"action":"top_up","stake_id":"1"}"#.to_string();
+        match contract.ft_on_transfer(account_id.clone(), U128::from(amount), msg) {
+            PromiseOrValue::Value(unused) => assert_eq!(u128::from(unused), 0),
+            _ => panic!("Expected a value"),
+        }
+
+        //the lock start moves halfway between both deposits and keeps its duration
+        let stake = contract.internal_get_stake(&account_id, U128::from(1));
+        assert_eq!(u128::from(stake.amount), amount * 2);
+        assert_eq!(stake.staked_at, 50);
+        assert_eq!(stake.duration, 180);
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot top up a stake of another FT")]
+    fn test_top_up_other_ft(){
+        let mut contract = get_contract();
+        let account_id = AccountId::try_from(ALICE.to_string()).unwrap();
+        let other_ft = AccountId::try_from("other.testnet".to_string()).unwrap();
+        contract.approved_ft_token_ids.insert(&other_ft);
+        let stake = get_stake(1, &account_id, "3months", 1000);
+        contract.staking_history.insert(&account_id, &vec![stake]);
+        testing_env!(VMContextBuilder::new().predecessor_account_id(other_ft).build());
+
+        let msg = r#"{"action":"top_up","stake_id":"1"}"#.to_string();
+        contract.ft_on_transfer(account_id, U128::from(1000), msg);
+    }
+
+    #[test]
+    fn test_claim_all(){
+        let mut contract = get_contract();
+        let ft_account_id = AccountId::try_from(FT_CONTRACT.to_string()).unwrap();
+        let account_id = AccountId::try_from(ALICE.to_string()).unwrap();
+        let amount: u128 = 20_000_000_000_000_000_000_000_000_000;
+        let stakes = vec![
+            get_stake(1, &account_id, "3months", amount),
+            get_stake(2, &account_id, "6months", amount * 2),
+        ];
+        contract.staking_history.insert(&account_id, &stakes);
+        let context = VMContextBuilder::new()
+            .predecessor_account_id(account_id.clone())
+            .block_timestamp(3600 * 1_000_000_000)
+            .build();
+        testing_env!(context.clone());
+        let rewards: Vec<u128> = stakes.iter().map(|stake| contract.internal_pending_reward(stake)).collect();
+        //both plans pay the same per minute, so rewards follow the amount
+        assert_eq!(rewards[1], rewards[0] * 2);
+        contract.claim_all(None);
+
+        let claimed_stakes: Vec<ClaimedStake> = stakes
+            .iter()
+            .zip(rewards.iter())
+            .map(|(stake, reward)| ClaimedStake{
+                stake_id : stake.stake_id,
+                unclaimed_reward : U128::from(0),
+                reward : U128::from(*reward),
+                claim_history : None,
+                claim_count : 60,
+            })
+            .collect();
+        //the claim is taken out before the transfer so it cannot be claimed twice
+        for stake in stakes.iter() {
+            assert_eq!(contract.internal_pending_reward(stake), 0);
+        }
+        //and given back when the transfer fails
+        set_promise_result(context.clone(), PromiseResult::Failed);
+        contract.claim_all_callback(account_id.clone(), claimed_stakes.clone(), Fees::new(&ft_account_id));
+        assert_eq!(u128::from(contract.internal_claimed_rewards(&account_id, &ft_account_id)), 0);
+        for (stake, reward) in stakes.iter().zip(rewards.iter()) {
+            assert_eq!(contract.internal_pending_reward(stake), *reward);
+        }
+
+        testing_env!(context.clone());
+        contract.claim_all(None);
+        set_promise_result(context, PromiseResult::Successful(vec![]));
+        contract.claim_all_callback(account_id.clone(), claimed_stakes, Fees::new(&ft_account_id));
+        let claimed = u128::from(contract.internal_claimed_rewards(&account_id, &ft_account_id));
+        assert_eq!(claimed, rewards[0] + rewards[1]);
+        for stake in stakes.iter() {
+            assert_eq!(contract.internal_pending_reward(stake), 0);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "No rewards to claim")]
+    fn test_claim_all_nothing_to_claim(){
+        let mut contract = get_contract();
+        let account_id = AccountId::try_from(ALICE.to_string()).unwrap();
+        let stake = get_stake(1, &account_id, "3months", 1000);
+        contract.staking_history.insert(&account_id, &vec![stake]);
+        //less than a minute has passed since the stake was made
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(account_id)
+            .block_timestamp(30 * 1_000_000_000)
+            .build());
+        contract.claim_all(None);
+    }
+
+    #[test]
+    fn test_unstake_matured(){
+        let mut contract = get_contract();
+        let ft_account_id = AccountId::try_from(FT_CONTRACT.to_string()).unwrap();
+        let account_id = AccountId::try_from(ALICE.to_string()).unwrap();
+        let amount: u128 = 10_000_000_000_000_000_000_000_000_000;
+        let matured = get_stake(1, &account_id, "3months", amount);
+        let mut locked = get_stake(2, &account_id, "3months", amount);
+        locked.duration = 7200;
+        contract.staking_history.insert(&account_id, &vec![matured.clone(), locked]);
+        let context = VMContextBuilder::new()
+            .predecessor_account_id(account_id.clone())
+            .block_timestamp(3600 * 1_000_000_000)
+            .build();
+        testing_env!(context.clone());
+        let reward = contract.internal_pending_reward(&matured);
+
+        //only the matured stake is taken out, the locked one stays
+        contract.unstake_matured(ft_account_id.clone());
+        let remaining = contract.staking_history.get(&account_id).unwrap();
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].stake_id, U128::from(2));
+
+        let mut withdrawn = matured.clone();
+        withdrawn.unclaimed_reward = U128::from(reward);
+        //a failed transfer gives the stake back
+        set_promise_result(context.clone(), PromiseResult::Failed);
+        contract.unstake_matured_callback(account_id.clone(), vec![withdrawn.clone()], Fees::new(&ft_account_id));
+        assert_eq!(contract.staking_history.get(&account_id).unwrap().len(), 2);
+
+        testing_env!(context.clone());
+        contract.unstake_matured(ft_account_id.clone());
+        set_promise_result(context, PromiseResult::Successful(vec![]));
+        contract.unstake_matured_callback(account_id.clone(), vec![withdrawn], Fees::new(&ft_account_id));
+        assert_eq!(contract.staking_history.get(&account_id).unwrap().len(), 1);
+        assert_eq!(u128::from(contract.internal_claimed_rewards(&account_id, &ft_account_id)), reward);
+    }
+
+    #[test]
+    #[should_panic(expected = "No matured stakes to withdraw")]
+    fn test_unstake_matured_locked(){
+        let mut contract = get_contract();
+        let ft_account_id = AccountId::try_from(FT_CONTRACT.to_string()).unwrap();
+        let account_id = AccountId::try_from(ALICE.to_string()).unwrap();
+        let stake = get_stake(1, &account_id, "3months", 1000);
+        contract.staking_history.insert(&account_id, &vec![stake]);
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(account_id)
+            .block_timestamp(60 * 1_000_000_000)
+            .build());
+        contract.unstake_matured(ft_account_id);
+    }
+
+    #[test]
+    fn test_operator_and_beneficiary(){
+        let mut contract = get_contract();
+        let alice = AccountId::try_from(ALICE.to_string()).unwrap();
+        let bob = AccountId::try_from("bob.testnet".to_string()).unwrap();
+        let carol = AccountId::try_from("carol.testnet".to_string()).unwrap();
+        let stake = get_stake(1, &alice, "3months", 10_000_000_000_000_000_000_000_000_000);
+        contract.staking_history.insert(&alice, &vec![stake]);
+        contract.stake_owners.insert(&U128::from(1), &alice);
+        testing_env!(VMContextBuilder::new().predecessor_account_id(alice.clone()).build());
+        contract.set_beneficiary(U128::from(1), Some(carol.clone()));
+        contract.set_operator(U128::from(1), Some(bob.clone()));
+
+        //payouts go to the beneficiary whoever triggers them
+        let (staker_id, stake) = contract.internal_get_managed_stake(&bob, U128::from(1));
+        assert_eq!(staker_id, alice);
+        assert_eq!(Contract::payout_receiver(&stake), carol);
+        assert_eq!(contract.internal_stake_owner(&stake), alice);
+
+        //the operator claims and unstakes for the staker
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(bob.clone())
+            .block_timestamp(3600 * 1_000_000_000)
+            .build());
+        contract.claim_reward(U128::from(1));
+        contract.ft_unstake(U128::from(1), None);
+        assert!(contract.staking_history.get(&bob).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "This user has not staked yet.")]
+    fn test_operator_removed(){
+        let mut contract = get_contract();
+        let alice = AccountId::try_from(ALICE.to_string()).unwrap();
+        let bob = AccountId::try_from("bob.testnet".to_string()).unwrap();
+        let stake = get_stake(1, &alice, "3months", 10_000_000_000_000_000_000_000_000_000);
+        contract.staking_history.insert(&alice, &vec![stake]);
+        contract.stake_owners.insert(&U128::from(1), &alice);
+        testing_env!(VMContextBuilder::new().predecessor_account_id(alice.clone()).build());
+        contract.set_operator(U128::from(1), Some(bob.clone()));
+        contract.set_operator(U128::from(1), None);
+
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(bob)
+            .block_timestamp(3600 * 1_000_000_000)
+            .build());
+        contract.claim_reward(U128::from(1));
+    }
+
+    #[test]
+    fn test_transfer_stake_moves_receipts(){
+        let mut contract = get_contract();
+        let ft_account_id = AccountId::try_from(FT_CONTRACT.to_string()).unwrap();
+        let alice = AccountId::try_from(ALICE.to_string()).unwrap();
+        let bob = AccountId::try_from("bob.testnet".to_string()).unwrap();
+        contract.receipt_ft_account_id = Some(ft_account_id.clone());
+        let mut stake = get_stake(1, &alice, "3months", 1000);
+        stake.receipt_amount = U128::from(contract.internal_mint_receipt(&ft_account_id, &alice, 1000));
+        contract.staking_history.insert(&alice, &vec![stake]);
+
+        contract.internal_transfer_stake(&alice, &bob, U128::from(1));
+        assert_eq!(contract.internal_receipt_balance(&alice), 0);
+        assert_eq!(contract.internal_receipt_balance(&bob), 1000);
+
+        //the new owner holds the receipts and can unstake
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(bob.clone())
+            .block_timestamp(3600 * 1_000_000_000)
+            .build());
+        contract.ft_unstake(U128::from(1), None);
+        assert_eq!(contract.internal_receipt_balance(&bob), 0);
+    }
+
+    #[test]
+    fn test_transfer_stake_plan_cap(){
+        let mut contract = get_contract();
+        let ft_account_id = AccountId::try_from(FT_CONTRACT.to_string()).unwrap();
+        let alice = AccountId::try_from(ALICE.to_string()).unwrap();
+        let bob = AccountId::try_from("bob.testnet".to_string()).unwrap();
+        let mut apy = contract.internal_get_apy(&ft_account_id, "3months");
+        apy.max_total_staked = Some(U128::from(1000));
+        contract.internal_set_apy(&ft_account_id, apy);
+        contract.internal_add_staked(&ft_account_id, "3months", 1000);
+        contract.staking_history.insert(&alice, &vec![get_stake(1, &alice, "3months", 1000)]);
+
+        //a full plan does not stop a stake from changing hands
+        contract.internal_transfer_stake(&alice, &bob, U128::from(1));
+        assert_eq!(contract.internal_plan_total(&ft_account_id, "3months"), 1000);
+    }
+
+    #[test]
+    #[should_panic(expected = "Staking cap of the receiver reached")]
+    fn test_transfer_stake_account_cap(){
+        let mut contract = get_contract();
+        let ft_account_id = AccountId::try_from(FT_CONTRACT.to_string()).unwrap();
+        let alice = AccountId::try_from(ALICE.to_string()).unwrap();
+        let bob = AccountId::try_from("bob.testnet".to_string()).unwrap();
+        let mut apy = contract.internal_get_apy(&ft_account_id, "3months");
+        apy.max_per_account = Some(U128::from(1500));
+        contract.internal_set_apy(&ft_account_id, apy);
+        contract.internal_add_staked(&ft_account_id, "3months", 2000);
+        contract.staking_history.insert(&alice, &vec![get_stake(1, &alice, "3months", 1000)]);
+        contract.staking_history.insert(&bob, &vec![get_stake(2, &bob, "3months", 1000)]);
+
+        contract.internal_transfer_stake(&alice, &bob, U128::from(1));
+    }
+
+    //a contract whose 3months plan has a ten minute unbonding period
+    fn get_unbonding_contract() -> Contract {
+        let mut contract = get_contract();
+        let ft_account_id = AccountId::try_from(FT_CONTRACT.to_string()).unwrap();
+        let mut apy = contract.internal_get_apy(&ft_account_id, "3months");
+        apy.unbonding_period = Some(600);
+        contract.internal_set_apy(&ft_account_id, apy);
+        contract
+    }
+
+    #[test]
+    fn test_unbonding(){
+        let mut contract = get_unbonding_contract();
+        let ft_account_id = AccountId::try_from(FT_CONTRACT.to_string()).unwrap();
+        let account_id = AccountId::try_from(ALICE.to_string()).unwrap();
+        let amount: u128 = 10_000_000_000_000_000_000_000_000_000;
+        let stake = get_stake(1, &account_id, "3months", amount);
+        contract.staking_history.insert(&account_id, &vec![stake.clone()]);
+        let context = |seconds: u64| VMContextBuilder::new()
+            .predecessor_account_id(account_id.clone())
+            .block_timestamp(seconds * 1_000_000_000)
+            .build();
+        testing_env!(context(3600));
+        let reward = contract.internal_pending_reward(&stake);
+        contract.request_unstake(U128::from(1));
+        assert!(contract.staking_history.get(&account_id).unwrap().is_empty());
+
+        //unbonding stakes stop earning and become withdrawable after the period
+        testing_env!(context(4000));
+        let totals = contract.get_unbonding_totals(account_id.clone());
+        assert_eq!(u128::from(totals[0].unbonding), amount + reward);
+        assert_eq!(u128::from(totals[0].withdrawable), 0);
+        testing_env!(context(4200));
+        let totals = contract.get_unbonding_totals(account_id.clone());
+        assert_eq!(u128::from(totals[0].unbonding), 0);
+        assert_eq!(u128::from(totals[0].withdrawable), amount + reward);
+
+        let entries = contract.unbonding.get(&account_id).unwrap();
+        contract.withdraw_unbonded();
+        assert!(contract.get_unbonding_totals(account_id.clone()).is_empty());
+        //a failed transfer puts the stake back in the queue
+        set_promise_result(context(4200), PromiseResult::Failed);
+        contract.withdraw_unbonded_callback(account_id.clone(), entries.clone(), Fees::new(&ft_account_id));
+        assert_eq!(contract.get_unbonding_totals(account_id.clone()).len(), 1);
+
+        testing_env!(context(4200));
+        contract.withdraw_unbonded();
+        set_promise_result(context(4200), PromiseResult::Successful(vec![]));
+        contract.withdraw_unbonded_callback(account_id.clone(), entries, Fees::new(&ft_account_id));
+        assert!(contract.get_unbonding_totals(account_id.clone()).is_empty());
+        assert_eq!(u128::from(contract.internal_claimed_rewards(&account_id, &ft_account_id)), reward);
+    }
+
+    #[test]
+    #[should_panic(expected = "No unbonded stakes to withdraw")]
+    fn test_withdraw_before_unbonded(){
+        let mut contract = get_unbonding_contract();
+        let account_id = AccountId::try_from(ALICE.to_string()).unwrap();
+        let stake = get_stake(1, &account_id, "3months", 10_000_000_000_000_000_000_000_000_000);
+        contract.staking_history.insert(&account_id, &vec![stake]);
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(account_id.clone())
+            .block_timestamp(3600 * 1_000_000_000)
+            .build());
+        contract.request_unstake(U128::from(1));
+        contract.withdraw_unbonded();
+    }
+
+    #[test]
+    fn test_stake_weight_boost(){
+        let mut contract = get_contract();
+        let ft_account_id = AccountId::try_from(FT_CONTRACT.to_string()).unwrap();
+        let account_id = AccountId::try_from(ALICE.to_string()).unwrap();
+        for (key, boost) in [("emission", None), ("boosted", Some(150))] {
+            let mut apy = APY::new(key.to_string(), U128::from(0), 0, 0);
+            apy.plan_type = PlanType::Emission;
+            apy.boost = boost;
+            contract.internal_set_apy(&ft_account_id, apy);
+        }
+        let mut pool = RewardPool::new(0);
+        pool.emission_per_second = U128::from(10);
+        contract.emission_pools.insert(&ft_account_id, &pool);
+
+        let mut plain = get_stake(1, &account_id, "emission", 1000);
+        let mut boosted = get_stake(2, &account_id, "boosted", 1000);
+        let boosted_apy = contract.internal_get_apy(&ft_account_id, "boosted");
+        assert_eq!(Contract::stake_weight(&plain, &contract.internal_get_apy(&ft_account_id, "emission")), 1000);
+        assert_eq!(Contract::stake_weight(&boosted, &boosted_apy), 1500);
+        contract.internal_join_pool(&mut plain);
+        contract.internal_join_pool(&mut boosted);
+
+        //the emission is shared by weight, not by amount
+        testing_env!(VMContextBuilder::new().block_timestamp(100 * 1_000_000_000).build());
+        assert_eq!(contract.internal_pending_reward(&plain), 400);
+        assert_eq!(contract.internal_pending_reward(&boosted), 600);
+
+        //a later change of the boost does not change what a stake takes out of the pool
+        let mut apy = boosted_apy;
+        apy.boost = Some(200);
+        contract.internal_set_apy(&ft_account_id, apy);
+        contract.internal_leave_pool(&mut boosted);
+        assert_eq!(u128::from(contract.internal_get_emission_pool(&ft_account_id).total_staked), 1000);
+        assert_eq!(u128::from(boosted.unclaimed_reward), 600);
+    }
+
+    #[test]
+    fn test_voting_power_per_token(){
+        let mut contract = get_contract();
+        let ft_account_id = AccountId::try_from(FT_CONTRACT.to_string()).unwrap();
+        let other_ft = AccountId::try_from("other.testnet".to_string()).unwrap();
+        let account_id = AccountId::try_from(ALICE.to_string()).unwrap();
+        let mut locked = get_stake(1, &account_id, "12months", 1000);
+        locked.duration = 31_536_000;
+        let mut renewing = get_stake(2, &account_id, "12months", 1000);
+        renewing.duration = 31_536_000;
+        renewing.auto_renew = true;
+        let mut other = get_stake(3, &account_id, "12months", 5000);
+        other.ft_account_id = other_ft.clone();
+        other.duration = 31_536_000;
+        contract.internal_save_stakes(&account_id, &vec![locked, renewing, other]);
+
+        //each token has its own power, with or without governance, and an auto renewing lock does not run down
+        assert!(contract.governance.is_none());
+        assert_eq!(contract.internal_voting_power(&account_id, &ft_account_id, 0), 2000);
+        assert_eq!(contract.internal_voting_power(&account_id, &ft_account_id, 31_536_000 / 2), 1500);
+        assert_eq!(contract.internal_voting_power(&account_id, &other_ft, 31_536_000 / 2), 2500);
+        assert_eq!(contract.get_voting_checkpoints(account_id, ft_account_id).len(), 1);
+    }
+
+    #[test]
+    fn test_tier_counts_all_positions(){
+        let mut contract = get_contract();
+        let ft_account_id = AccountId::try_from(FT_CONTRACT.to_string()).unwrap();
+        let account_id = AccountId::try_from(ALICE.to_string()).unwrap();
+        contract.tiers = vec![Tier{ name : "Bronze".to_string(), min_staked : U128::from(100) }];
+        let tokens: u128 = 1_000_000_000_000_000_000_000_000;
+
+        //the decimals of the FT are used, not the ones sent with the stake
+        let mut stake = get_stake(1, &account_id, "3months", 50 * tokens);
+        stake.decimal = 0;
+        contract.staking_history.insert(&account_id, &vec![stake]);
+        assert_eq!(contract.internal_total_staked(&account_id), 50);
+
+        contract.unbonding.insert(&account_id, &vec![Unbonding{
+            stake : get_stake(2, &account_id, "3months", 30 * tokens),
+            requested_at : 0,
+            unbonds_at : 600,
+        }]);
+        let mut apy = APY::new("flexible".to_string(), U128::from(0), 0, 500);
+        apy.plan_type = PlanType::Flexible;
+        contract.internal_set_apy(&ft_account_id, apy);
+        let mut position = PoolPosition::new();
+        position.amount = U128::from(20 * tokens);
+        contract.pool_positions.insert(&(account_id.clone(), ft_account_id, "flexible".to_string()), &position);
+        assert_eq!(contract.get_tier(account_id).unwrap().name, "Bronze");
+    }
+
+    #[test]
+    fn test_referral_chain_cap(){
+        let mut contract = get_contract();
+        let ft_account_id = AccountId::try_from(FT_CONTRACT.to_string()).unwrap();
+        let accounts: Vec<AccountId> = (0..22)
+            .map(|index| AccountId::try_from(format!("account{}.testnet", index)).unwrap())
+            .collect();
+        //long chains are only checked up to the cap
+        for pair in accounts.windows(2) {
+            contract.internal_set_referrer(&pair[1], &ft_account_id, Some(pair[0].clone()));
+        }
+        assert_eq!(contract.get_referrer(accounts[21].clone()), Some(accounts[20].clone()));
+    }
+
+    #[test]
+    fn test_referral_on_every_payout(){
+        let mut contract = get_contract();
+        let ft_account_id = AccountId::try_from(FT_CONTRACT.to_string()).unwrap();
+        let alice = AccountId::try_from(ALICE.to_string()).unwrap();
+        let bob = AccountId::try_from("bob.testnet".to_string()).unwrap();
+        contract.referral_configs.insert(&ft_account_id, &ReferralConfig{
+            reward_bps : 500,
+            flat_bonus : U128::from(0),
+        });
+
+        //rewards paid with the principal
+        let mut stake = get_stake(1, &alice, "3months", 1000);
+        stake.referrer = Some(bob.clone());
+        stake.unclaimed_reward = U128::from(1000);
+        contract.internal_withdrawn_rewards(&alice, &stake);
+        assert_eq!(u128::from(contract.get_referral_rewards(bob.clone(), ft_account_id.clone())), 50);
+
+        //rewards compounded into the stake
+        let mut apy = contract.internal_get_apy(&ft_account_id, "3months");
+        apy.auto_compound = true;
+        contract.internal_set_apy(&ft_account_id, apy);
+        let mut stake = get_stake(2, &alice, "3months", 10_000_000_000_000_000_000_000_000_000);
+        stake.referrer = Some(bob.clone());
+        testing_env!(VMContextBuilder::new().block_timestamp(3600 * 1_000_000_000).build());
+        let compounded = contract.internal_pending_reward(&stake);
+        contract.internal_leave_pool(&mut stake);
+        assert_eq!(
+            u128::from(contract.get_referral_rewards(bob.clone(), ft_account_id.clone())),
+            50 + compounded / 20
+        );
+
+        //held back rewards of denied accounts pay nothing
+        contract.denylist.insert(&alice);
+        let mut stake = get_stake(3, &alice, "3months", 1000);
+        stake.referrer = Some(bob.clone());
+        stake.unclaimed_reward = U128::from(1000);
+        contract.internal_withdrawn_rewards(&alice, &stake);
+        assert_eq!(
+            u128::from(contract.get_referral_rewards(bob, ft_account_id)),
+            50 + compounded / 20
+        );
+    }
+
+    #[test]
+    fn test_auto_compound_liabilities(){
+        let mut contract = get_contract();
+        let ft_account_id = AccountId::try_from(FT_CONTRACT.to_string()).unwrap();
+        let account_id = AccountId::try_from(ALICE.to_string()).unwrap();
+        let mut apy = contract.internal_get_apy(&ft_account_id, "3months");
+        apy.auto_compound = true;
+        contract.internal_set_apy(&ft_account_id, apy);
+        let amount: u128 = 10_000_000_000_000_000_000_000_000_000;
+        testing_env!(VMContextBuilder::new().predecessor_account_id(ft_account_id.clone()).build());
+        let msg = format!(
+            r#"{{"ft_symbol":"FT","ft_account_id":"{}","decimal":24,"duration":180,"staked_by":"{}","staking_plan":"3months"}}"#,
+            FT_CONTRACT, ALICE
+        );
+        contract.ft_on_transfer(account_id.clone(), U128::from(amount), msg);
+        let stake = contract.staking_history.get(&account_id).unwrap()[0].clone();
+
+        //the plan owes what the stake compounded
+        let context = VMContextBuilder::new()
+            .predecessor_account_id(account_id.clone())
+            .block_timestamp(3600 * 1_000_000_000)
+            .build();
+        testing_env!(context.clone());
+        let compounded = contract.internal_pending_reward(&stake);
+        assert!(compounded > 0);
+        let stats = contract.get_plan_stats(ft_account_id.clone(), "3months".to_string());
+        assert_eq!(u128::from(stats.pending_liabilities), compounded);
+
+        //and nothing once it is paid with the unstake
+        contract.ft_unstake(stake.stake_id, None);
+        set_promise_result(context, PromiseResult::Successful(vec![]));
+        let unstaked = UnstakedAmount{
+            amount : None,
+            receipt_amount : stake.receipt_amount,
+        };
+        contract.unstake_callback(stake.stake_id, account_id, unstaked, Fees::new(&ft_account_id));
+        let stats = contract.get_plan_stats(ft_account_id, "3months".to_string());
+        assert_eq!(u128::from(stats.pending_liabilities), 0);
+        assert_eq!(u128::from(stats.rewards_paid), compounded);
+        assert_eq!(u128::from(stats.total_staked), 0);
+    }
+
+    #[test]
+    fn test_account_summary_emission(){
+        let mut contract = get_contract();
+        let ft_account_id = AccountId::try_from(FT_CONTRACT.to_string()).unwrap();
+        let account_id = AccountId::try_from(ALICE.to_string()).unwrap();
+        let mut apy = APY::new("emission".to_string(), U128::from(0), 0, 0);
+        apy.plan_type = PlanType::Emission;
+        contract.internal_set_apy(&ft_account_id, apy);
+        let mut pool = RewardPool::new(0);
+        pool.emission_per_second = U128::from(10);
+        contract.emission_pools.insert(&ft_account_id, &pool);
+        let mut stake = get_stake(1, &account_id, "emission", 1000);
+        contract.internal_join_pool(&mut stake);
+        contract.staking_history.insert(&account_id, &vec![stake]);
+
+        //emission rewards are settled by claim_reward, they can be claimed within the first minute
+        testing_env!(VMContextBuilder::new().block_timestamp(30 * 1_000_000_000).build());
+        let summary = contract.get_account_summary(account_id);
+        assert_eq!(u128::from(summary[0].claimable), 300);
+        assert_eq!(u128::from(summary[0].stakes[0].claimable_reward), 300);
+    }
+
+    #[test]
+    fn test_settle_reward_partial_minute(){
+        let mut contract = get_contract();
+        let account_id = AccountId::try_from(ALICE.to_string()).unwrap();
+        let amount: u128 = 1_000_000_000_000_000_000_000_000_000;
+        let mut stake = get_stake(1, &account_id, "3months", amount);
+        testing_env!(VMContextBuilder::new().block_timestamp(90 * 1_000_000_000).build());
+        contract.internal_settle_reward(&mut stake);
+        assert_eq!(contract.claim_history.get(&stake.stake_id).unwrap().last_claimed_at, 60);
+
+        //the half minute left over at the settlement still counts
+        testing_env!(VMContextBuilder::new().block_timestamp(120 * 1_000_000_000).build());
+        let apy = contract.internal_get_apy(&stake.ft_account_id, &stake.staking_plan);
+        assert_eq!(contract.internal_pending_reward(&stake), Contract::simple_reward(amount, &apy, 2));
+    }
+
+    #[test]
+    fn test_claim_reward_failed_transfer(){
+        let mut contract = get_contract();
+        let ft_account_id = AccountId::try_from(FT_CONTRACT.to_string()).unwrap();
+        let account_id = AccountId::try_from(ALICE.to_string()).unwrap();
+        let amount: u128 = 1_000_000_000_000_000_000_000_000_000;
+        let mut stake = get_stake(1, &account_id, "3months", amount);
+        stake.unclaimed_reward = U128::from(1000);
+        contract.staking_history.insert(&account_id, &vec![stake.clone()]);
+        let context = VMContextBuilder::new()
+            .predecessor_account_id(account_id.clone())
+            .block_timestamp(3600 * 1_000_000_000)
+            .build();
+        testing_env!(context.clone());
+        let reward = contract.internal_pending_reward(&stake);
+        contract.claim_reward(U128::from(1));
+        let claimed = contract.internal_get_stake(&account_id, U128::from(1));
+        assert_eq!(u128::from(claimed.unclaimed_reward), 0);
+        assert_eq!(contract.internal_pending_reward(&claimed), 0);
+
+        set_promise_result(context, PromiseResult::Failed);
+        let claimed = ClaimedStake{
+            stake_id : U128::from(1),
+            unclaimed_reward : U128::from(1000),
+            reward : U128::from(reward),
+            claim_history : None,
+            claim_count : 60,
+        };
+        contract.claim_reward_callback(account_id.clone(), claimed, Fees::new(&ft_account_id));
+        let restored = contract.internal_get_stake(&account_id, U128::from(1));
+        assert_eq!(u128::from(restored.unclaimed_reward), 1000);
+        assert_eq!(contract.internal_pending_reward(&restored), reward);
+        assert!(contract.claim_history.get(&U128::from(1)).is_none());
+    }
+
+    #[test]
+    fn test_set_operator_keeps_owner(){
+        let mut contract = get_contract();
+        let alice = AccountId::try_from(ALICE.to_string()).unwrap();
+        let bob = AccountId::try_from("bob.testnet".to_string()).unwrap();
+        let stake = get_stake(1, &alice, "3months", 1000);
+        contract.staking_history.insert(&alice, &vec![stake]);
+        testing_env!(VMContextBuilder::new().predecessor_account_id(alice).build());
+        //changing the operator does not touch the ownership index
+        contract.set_operator(U128::from(1), Some(bob));
+        assert!(contract.stake_owners.get(&U128::from(1)).is_none());
+    }
+
+    #[test]
+    fn test_nft_resolve_transfer_returns_denied(){
+        use near_contract_standards::non_fungible_token::core::{NonFungibleTokenCore, NonFungibleTokenResolver};
+        let mut contract = get_contract();
+        let alice = AccountId::try_from(ALICE.to_string()).unwrap();
+        let bob = AccountId::try_from("bob.testnet".to_string()).unwrap();
+        let stake = get_stake(1, &alice, "3months", 1000);
+        contract.staking_history.insert(&alice, &vec![stake]);
+        contract.stake_owners.insert(&U128::from(1), &alice);
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(alice.clone())
+            .attached_deposit(1)
+            .build());
+        let _ = contract.nft_transfer_call(bob.clone(), "1".to_string(), None, None, "".to_string());
+        assert_eq!(contract.stake_owners.get(&U128::from(1)), Some(bob.clone()));
+
+        //a transfer back to alice would be refused now, handing the stake back must not be
+        contract.denylist.insert(&alice);
+        set_promise_result(
+            VMContextBuilder::new().predecessor_account_id(alice.clone()).build(),
+            PromiseResult::Successful(b"true".to_vec()),
+        );
+        assert!(!contract.nft_resolve_transfer(alice.clone(), bob.clone(), "1".to_string(), None));
+        assert_eq!(contract.stake_owners.get(&U128::from(1)), Some(alice.clone()));
+        assert!(contract.staking_history.get(&bob).unwrap().is_empty());
+        assert_eq!(contract.internal_get_stake(&alice, U128::from(1)).staked_by, alice);
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot close the receipt account while it has stakes")]
+    fn test_force_unregister_with_stakes(){
+        use near_contract_standards::storage_management::StorageManagement;
+        let mut contract = get_contract();
+        let ft_account_id = AccountId::try_from(FT_CONTRACT.to_string()).unwrap();
+        let alice = AccountId::try_from(ALICE.to_string()).unwrap();
+        let mut stake = get_stake(1, &alice, "3months", 1000);
+        contract.receipt_ft_account_id = Some(ft_account_id.clone());
+        stake.receipt_amount = U128::from(contract.internal_mint_receipt(&ft_account_id, &alice, 1000));
+        contract.staking_history.insert(&alice, &vec![stake]);
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(alice)
+            .attached_deposit(1)
+            .build());
+        contract.storage_unregister(Some(true));
+    }
+
+    //governance of the FT with a year long lock of 1000 tokens for alice, made before the block at 200 seconds
+    fn get_governed_contract() -> Contract {
+        let mut contract = get_contract();
+        let ft_account_id = AccountId::try_from(FT_CONTRACT.to_string()).unwrap();
+        let alice = AccountId::try_from(ALICE.to_string()).unwrap();
+        contract.governance = Some(GovernanceConfig{
+            ft_account_id,
+            voting_period : 600,
+            quorum : U128::from(0),
+            proposal_threshold : U128::from(0),
+        });
+        let mut locked = get_stake(1, &alice, "12months", 1000);
+        locked.duration = 31_536_000;
+        testing_env!(VMContextBuilder::new().block_timestamp(100 * 1_000_000_000).build());
+        contract.internal_save_stakes(&alice, &vec![locked]);
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(alice)
+            .block_timestamp(200 * 1_000_000_000)
+            .build());
+        contract
+    }
+
+    fn other_ft() -> FT {
+        FT{
+            account_id : AccountId::try_from("other.testnet".to_string()).unwrap(),
+            symbol : "OTHER".to_string(),
+            apy_against_duration : None,
+            max_total_staked : None,
+            decimals : Some(24),
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "No voting power at the proposal snapshot")]
+    fn test_vote_stake_in_proposal_block(){
+        let mut contract = get_governed_contract();
+        let bob = AccountId::try_from("bob.testnet".to_string()).unwrap();
+        let id = contract.create_proposal("Approve OTHER".to_string(), ProposalKind::ApproveFt{ ft : other_ft(), ft_apy : vec![] });
+
+        //a lock made in the block of the proposal does not count
+        let mut locked = get_stake(2, &bob, "12months", 1000);
+        locked.duration = 31_536_000;
+        contract.internal_save_stakes(&bob, &vec![locked]);
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(bob)
+            .block_timestamp(200 * 1_000_000_000)
+            .build());
+        contract.vote(id, true);
+    }
+
+    #[test]
+    #[should_panic(expected = "FT is already approved")]
+    fn test_create_invalid_proposal(){
+        let mut contract = get_governed_contract();
+        let mut ft = other_ft();
+        ft.account_id = AccountId::try_from(FT_CONTRACT.to_string()).unwrap();
+        contract.create_proposal("Approve FT again".to_string(), ProposalKind::ApproveFt{ ft, ft_apy : vec![] });
+    }
+
+    #[test]
+    fn test_execute_invalid_proposal(){
+        let mut contract = get_governed_contract();
+        let alice = AccountId::try_from(ALICE.to_string()).unwrap();
+        let id = contract.create_proposal("Approve OTHER".to_string(), ProposalKind::ApproveFt{ ft : other_ft(), ft_apy : vec![] });
+        contract.vote(id, true);
+
+        //the owner approved the FT while the proposal was voted on, it fails instead of getting stuck
+        contract.internal_approve_ft(other_ft(), vec![]);
+        testing_env!(VMContextBuilder::new()
+            .predecessor_account_id(alice)
+            .block_timestamp(800 * 1_000_000_000)
+            .build());
+        assert_eq!(contract.execute_proposal(id), ProposalStatus::Failed);
+        assert_eq!(contract.get_proposal(id).unwrap().status, ProposalStatus::Failed);
+    }
 }
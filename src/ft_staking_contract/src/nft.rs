@@ -0,0 +1,236 @@
+use crate::*;
+
+use near_contract_standards::non_fungible_token::core::{
+    NonFungibleTokenCore, NonFungibleTokenResolver,
+};
+use near_contract_standards::non_fungible_token::metadata::{
+    NFTContractMetadata, NonFungibleTokenMetadataProvider, TokenMetadata, NFT_METADATA_SPEC,
+};
+use near_contract_standards::non_fungible_token::{Token, TokenId};
+use near_sdk::{ext_contract, Gas, PromiseOrValue, PromiseResult};
+
+const GAS_FOR_NFT_ON_TRANSFER: Gas = Gas(25_000_000_000_000);
+const GAS_FOR_RESOLVE_TRANSFER: Gas = Gas(10_000_000_000_000);
+
+#[ext_contract(ext_nft_receiver)]
+trait NonFungibleTokenReceiver {
+    fn nft_on_transfer(
+        &mut self,
+        sender_id: AccountId,
+        previous_owner_id: AccountId,
+        token_id: TokenId,
+        msg: String,
+    ) -> PromiseOrValue<bool>;
+}
+
+#[ext_contract(ext_nft_resolver)]
+trait NonFungibleTokenResolverSender {
+    fn nft_resolve_transfer(
+        &mut self,
+        previous_owner_id: AccountId,
+        receiver_id: AccountId,
+        token_id: TokenId,
+        approved_account_ids: Option<HashMap<AccountId, u64>>,
+    ) -> bool;
+}
+
+impl Contract {
+    //every stake is a NEP-171 token with the stake id as token id
+    pub(crate) fn stake_token(&self, owner_id: AccountId, stake: &Stake) -> Token {
+        let current_time = env::block_timestamp() / 1000000000;
+        let unlocks_at = Contract::unlocks_at(stake, current_time);
+        let extra = near_sdk::serde_json::json!({
+            "ft_account_id": stake.ft_account_id,
+            "amount": stake.amount,
+            "staking_plan": stake.staking_plan,
+            "staked_at": stake.staked_at,
+            "unlocks_at": unlocks_at,
+        });
+        let metadata = TokenMetadata {
+            title: Some(format!("Stake #{}", u128::from(stake.stake_id))),
+            description: Some(format!(
+                "{} {} staked on the {} plan, unlocks at {}",
+                u128::from(stake.amount),
+                stake.ft_symbol,
+                stake.staking_plan,
+                unlocks_at
+            )),
+            media: None,
+            media_hash: None,
+            copies: Some(1),
+            issued_at: Some(stake.staked_at.to_string()),
+            expires_at: None,
+            starts_at: None,
+            updated_at: None,
+            extra: Some(extra.to_string()),
+            reference: None,
+            reference_hash: None,
+        };
+        Token {
+            token_id: u128::from(stake.stake_id).to_string(),
+            owner_id,
+            metadata: Some(metadata),
+            approved_account_ids: None,
+        }
+    }
+
+    pub(crate) fn parse_token_id(token_id: &TokenId) -> StakeId {
+        U128::from(token_id.parse::<u128>().expect("Invalid token id"))
+    }
+}
+
+//the owner part of NEP-181, stakes are only indexed per account so there is no global enumeration
+#[near_bindgen]
+impl Contract {
+    pub fn nft_supply_for_owner(&self, account_id: AccountId) -> U128 {
+        U128::from(self.staking_history.get(&account_id).map_or(0, |stakes| stakes.len()) as u128)
+    }
+
+    pub fn nft_tokens_for_owner(
+        &self,
+        account_id: AccountId,
+        from_index: Option<U128>,
+        limit: Option<u64>,
+    ) -> Vec<Token> {
+        let stakes = self.staking_history.get(&account_id).unwrap_or_default();
+        let start = u128::from(from_index.unwrap_or(U128(0))) as usize;
+        stakes
+            .iter()
+            .skip(start)
+            .take(limit.unwrap_or(stakes.len() as u64) as usize)
+            .map(|stake| self.stake_token(account_id.clone(), stake))
+            .collect()
+    }
+}
+
+#[near_bindgen]
+impl NonFungibleTokenCore for Contract {
+    #[payable]
+    fn nft_transfer(
+        &mut self,
+        receiver_id: AccountId,
+        token_id: TokenId,
+        approval_id: Option<u64>,
+        memo: Option<String>,
+    ) {
+        near_sdk::assert_one_yocto();
+        assert!(approval_id.is_none(), "Approvals are not supported");
+        let sender_id = env::predecessor_account_id();
+        let stake_id = Contract::parse_token_id(&token_id);
+        if let Some(memo) = memo {
+            log!("Memo: {}", memo);
+        }
+        self.internal_transfer_stake(&sender_id, &receiver_id, stake_id);
+    }
+
+    #[payable]
+    fn nft_transfer_call(
+        &mut self,
+        receiver_id: AccountId,
+        token_id: TokenId,
+        approval_id: Option<u64>,
+        memo: Option<String>,
+        msg: String,
+    ) -> PromiseOrValue<bool> {
+        near_sdk::assert_one_yocto();
+        assert!(approval_id.is_none(), "Approvals are not supported");
+        let sender_id = env::predecessor_account_id();
+        let stake_id = Contract::parse_token_id(&token_id);
+        if let Some(memo) = memo {
+            log!("Memo: {}", memo);
+        }
+        self.internal_transfer_stake(&sender_id, &receiver_id, stake_id);
+
+        ext_nft_receiver::nft_on_transfer(
+            sender_id.clone(),
+            sender_id.clone(),
+            token_id.clone(),
+            msg,
+            receiver_id.clone(),
+            0,
+            GAS_FOR_NFT_ON_TRANSFER,
+        )
+        .then(ext_nft_resolver::nft_resolve_transfer(
+            sender_id,
+            receiver_id,
+            token_id,
+            None,
+            env::current_account_id(),
+            0,
+            GAS_FOR_RESOLVE_TRANSFER,
+        ))
+        .into()
+    }
+
+    fn nft_token(&self, token_id: TokenId) -> Option<Token> {
+        let stake_id = Contract::parse_token_id(&token_id);
+        let owner_id = self.stake_owners.get(&stake_id)?;
+        let stake = self
+            .staking_history
+            .get(&owner_id)?
+            .into_iter()
+            .find(|i| i.stake_id == stake_id)?;
+        Some(self.stake_token(owner_id, &stake))
+    }
+}
+
+#[near_bindgen]
+impl NonFungibleTokenResolver for Contract {
+    #[private]
+    fn nft_resolve_transfer(
+        &mut self,
+        previous_owner_id: AccountId,
+        receiver_id: AccountId,
+        token_id: TokenId,
+        approved_account_ids: Option<HashMap<AccountId, u64>>,
+    ) -> bool {
+        //approvals are not supported so there are none to restore
+        let _ = approved_account_ids;
+        //the receiver asks for the token back by returning true
+        let must_revert = match env::promise_result(0) {
+            PromiseResult::Successful(value) => {
+                near_sdk::serde_json::from_slice::<bool>(&value).unwrap_or(true)
+            }
+            _ => true,
+        };
+        if !must_revert {
+            return true;
+        }
+
+        let stake_id = Contract::parse_token_id(&token_id);
+        if self.stake_owners.get(&stake_id).as_ref() != Some(&receiver_id) {
+            //the receiver already moved or withdrew the stake
+            return true;
+        }
+        let stake = match self
+            .staking_history
+            .get(&receiver_id)
+            .and_then(|stakes| stakes.into_iter().find(|i| i.stake_id == stake_id))
+        {
+            Some(stake) => stake,
+            None => return true,
+        };
+        if self.internal_receipt_balance(&receiver_id) < u128::from(stake.receipt_amount) {
+            //the receipts were passed on, the stake stays where they can be found
+            return true;
+        }
+        //the stake goes back where it was before the call, the checks of a transfer were done by nft_transfer_call
+        self.internal_move_stake(&receiver_id, &previous_owner_id, stake);
+        false
+    }
+}
+
+#[near_bindgen]
+impl NonFungibleTokenMetadataProvider for Contract {
+    fn nft_metadata(&self) -> NFTContractMetadata {
+        NFTContractMetadata {
+            spec: NFT_METADATA_SPEC.to_string(),
+            name: "Staking Positions".to_string(),
+            symbol: "STAKE".to_string(),
+            icon: None,
+            base_uri: None,
+            reference: None,
+            reference_hash: None,
+        }
+    }
+}
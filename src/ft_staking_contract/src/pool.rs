@@ -0,0 +1,241 @@
+use crate::*;
+
+pub(crate) const SECONDS_PER_YEAR: u128 = 31_536_000;
+
+//shared reward accumulator, rewards of a position are amount * reward_per_share minus its reward debt
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RewardPool {
+    pub total_staked: U128,
+    pub reward_per_share: U128, //scaled by REWARD_PRECISION
+    pub last_reward_at: u64,
+    pub emission_per_second: U128, //tokens shared between all stakes of an emission pool
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PoolPosition {
+    pub amount: U128,
+    pub reward_debt: U128,
+    pub pending_reward: U128, //settled rewards waiting to be claimed
+}
+
+impl RewardPool {
+    pub(crate) fn new(current_time: u64) -> Self {
+        Self {
+            total_staked: U128::from(0),
+            reward_per_share: U128::from(0),
+            last_reward_at: current_time,
+            emission_per_second: U128::from(0),
+        }
+    }
+
+    //adds the rewards of the time passed since the last update, interest_rate is yearly: 10% = 1000
+    pub(crate) fn update(&mut self, interest_rate: u16, current_time: u64) {
+        if current_time <= self.last_reward_at {
+            return;
+        }
+        let elapsed = (current_time - self.last_reward_at) as u128;
+        let mut increase = Contract::mul_div(
+            interest_rate as u128 * elapsed,
+            REWARD_PRECISION,
+            10000 * SECONDS_PER_YEAR,
+        );
+        //nothing is emitted while the pool is empty
+        let total_staked = u128::from(self.total_staked);
+        if total_staked > 0 {
+            increase += Contract::mul_div(
+                u128::from(self.emission_per_second) * elapsed,
+                REWARD_PRECISION,
+                total_staked,
+            );
+        }
+        self.reward_per_share = U128::from(u128::from(self.reward_per_share) + increase);
+        self.last_reward_at = current_time;
+    }
+
+    pub(crate) fn accumulated(&self, amount: u128) -> u128 {
+        Contract::mul_div(amount, u128::from(self.reward_per_share), REWARD_PRECISION)
+    }
+}
+
+impl PoolPosition {
+    pub(crate) fn new() -> Self {
+        Self {
+            amount: U128::from(0),
+            reward_debt: U128::from(0),
+            pending_reward: U128::from(0),
+        }
+    }
+
+    //moves the rewards accrued since the last change into pending_reward
+    pub(crate) fn settle(&mut self, pool: &RewardPool) {
+        let accumulated = pool.accumulated(u128::from(self.amount));
+        let reward = accumulated - u128::from(self.reward_debt);
+        self.pending_reward = U128::from(u128::from(self.pending_reward) + reward);
+        self.reward_debt = U128::from(accumulated);
+    }
+
+    pub(crate) fn set_amount(&mut self, amount: u128, pool: &RewardPool) {
+        self.amount = U128::from(amount);
+        self.reward_debt = U128::from(pool.accumulated(amount));
+    }
+}
+
+impl Contract {
+    //a pool with its rewards updated to the current time
+    pub(crate) fn internal_get_pool(&self, ft_account_id: &AccountId, staking_plan: &str) -> (RewardPool, APY) {
+        let apy = self.internal_get_apy(ft_account_id, staking_plan);
+        assert!(
+            apy.plan_type == PlanType::Flexible,
+            "This staking plan is not flexible"
+        );
+        let current_time = env::block_timestamp() / 1000000000;
+        let mut pool = self
+            .reward_pools
+            .get(&(ft_account_id.clone(), staking_plan.to_string()))
+            .unwrap_or_else(|| RewardPool::new(current_time));
+        pool.update(apy.interest_rate, current_time);
+        (pool, apy)
+    }
+
+    //the emission pool of a token with its rewards updated to the current time
+    pub(crate) fn internal_get_emission_pool(&self, ft_account_id: &AccountId) -> RewardPool {
+        let current_time = env::block_timestamp() / 1000000000;
+        let mut pool = self
+            .emission_pools
+            .get(ft_account_id)
+            .unwrap_or_else(|| RewardPool::new(current_time));
+        pool.update(0, current_time);
+        pool
+    }
+
+    //share of a stake in the emission pool of its token, longer plans can boost it
+    pub(crate) fn stake_weight(stake: &Stake, apy: &APY) -> u128 {
+        let boost = apy.boost.unwrap_or(100) as u128;
+        Contract::mul_div(u128::from(stake.amount), boost, 100)
+    }
+
+    //settles the rewards of a stake and takes its weight out of the emission pool before the stake changes
+    pub(crate) fn internal_leave_pool(&mut self, stake: &mut Stake) {
+        //counted at the amount it joined with, compounding is added when it joins again
+        let amount = u128::from(stake.amount);
+        self.internal_remove_staked(&stake.ft_account_id, &stake.staking_plan, amount);
+        self.internal_settle_reward(stake);
+        //compounded rewards are paid into the stake, denied accounts forfeit them and keep their principal
+        let compounded = u128::from(stake.amount).saturating_sub(amount);
+        if self.denylist.contains(&self.internal_stake_owner(stake)) {
+            stake.amount = U128::from(amount);
+            self.internal_release_rewards(&stake.ft_account_id, &stake.staking_plan, compounded, false);
+        } else {
+            self.internal_release_rewards(&stake.ft_account_id, &stake.staking_plan, compounded, true);
+            self.internal_accrue_referral(stake.referrer.as_ref(), &stake.ft_account_id, compounded);
+        }
+        let apy = self.internal_get_apy(&stake.ft_account_id, &stake.staking_plan);
+        if apy.plan_type != PlanType::Emission {
+            return;
+        }
+        let weight = u128::from(stake.weight);
+        self.internal_change_plan_weight(&stake.ft_account_id, &stake.staking_plan, weight, false);
+        let mut pool = self.internal_get_emission_pool(&stake.ft_account_id);
+        let total = u128::from(pool.total_staked) - weight;
+        pool.total_staked = U128::from(total);
+        stake.weight = U128::from(0);
+        self.emission_pools.insert(&stake.ft_account_id, &pool);
+    }
+
+    //adds the weight of a stake to the emission pool, rewards start accruing from now
+    pub(crate) fn internal_join_pool(&mut self, stake: &mut Stake) {
+        self.internal_add_staked(&stake.ft_account_id, &stake.staking_plan, u128::from(stake.amount));
+        let apy = self.internal_get_apy(&stake.ft_account_id, &stake.staking_plan);
+        if apy.plan_type != PlanType::Emission {
+            return;
+        }
+        let mut pool = self.internal_get_emission_pool(&stake.ft_account_id);
+        //the weight is kept on the stake so a later change of the boost cannot unbalance the pool
+        let weight = Contract::stake_weight(stake, &apy);
+        stake.weight = U128::from(weight);
+        self.internal_change_plan_weight(&stake.ft_account_id, &stake.staking_plan, weight, true);
+        pool.total_staked = U128::from(u128::from(pool.total_staked) + weight);
+        stake.reward_debt = U128::from(pool.accumulated(weight));
+        self.emission_pools.insert(&stake.ft_account_id, &pool);
+    }
+
+    pub(crate) fn internal_get_position(
+        &self,
+        account_id: &AccountId,
+        ft_account_id: &AccountId,
+        staking_plan: &str,
+    ) -> PoolPosition {
+        self.pool_positions
+            .get(&(account_id.clone(), ft_account_id.clone(), staking_plan.to_string()))
+            .unwrap_or_else(PoolPosition::new)
+    }
+
+    pub(crate) fn internal_save_position(
+        &mut self,
+        account_id: &AccountId,
+        ft_account_id: &AccountId,
+        staking_plan: &str,
+        pool: &RewardPool,
+        position: &PoolPosition,
+    ) {
+        self.reward_pools
+            .insert(&(ft_account_id.clone(), staking_plan.to_string()), pool);
+        self.pool_positions.insert(
+            &(account_id.clone(), ft_account_id.clone(), staking_plan.to_string()),
+            position,
+        );
+    }
+
+    pub(crate) fn internal_flexible_deposit(
+        &mut self,
+        sender_id: &AccountId,
+        ft_account_id: &AccountId,
+        staking_plan: &str,
+        amount: U128,
+        referrer: Option<AccountId>,
+    ) -> u128 {
+        let calling_contrat = env::predecessor_account_id();
+        assert!(
+            self.approved_ft_token_ids.contains(&calling_contrat),
+            "Only approved FT can be staked"
+        );
+        assert_eq!(
+            &calling_contrat, ft_account_id,
+            "Cannot stake tokens of another FT"
+        );
+        let (mut pool, apy) = self.internal_get_pool(ft_account_id, staking_plan);
+        Contract::assert_plan_open(&apy, env::block_timestamp() / 1000000000);
+        let accepted = self.internal_accept_deposit(sender_id, ft_account_id, staking_plan, u128::from(amount));
+        let unused = u128::from(amount) - accepted;
+        let amount = U128::from(self.internal_take_deposit_fee(ft_account_id, accepted));
+        let threshold: u128 = u128::from(apy.min_staking_amount);
+        assert!(
+            u128::from(amount) >= threshold,
+            "Cannot stake less than {} tokens",
+            threshold
+        );
+        self.assert_can_stake(sender_id, ft_account_id, &apy);
+        self.internal_set_referrer(sender_id, ft_account_id, referrer);
+
+        let mut position = self.internal_get_position(sender_id, ft_account_id, staking_plan);
+        if u128::from(position.amount) == 0 {
+            self.internal_track_stake(sender_id, ft_account_id, staking_plan, true);
+        }
+        position.settle(&pool);
+        let total = u128::from(position.amount) + u128::from(amount);
+        position.set_amount(total, &pool);
+        pool.total_staked = U128::from(u128::from(pool.total_staked) + u128::from(amount));
+        self.internal_save_position(sender_id, ft_account_id, staking_plan, &pool, &position);
+        self.internal_add_staked(ft_account_id, staking_plan, u128::from(amount));
+
+        log!(
+            "{:?} staked by {} in the flexible {} plan",
+            amount,
+            sender_id,
+            staking_plan
+        );
+        unused
+    }
+}
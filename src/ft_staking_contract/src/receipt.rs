@@ -0,0 +1,90 @@
+use crate::*;
+
+use near_contract_standards::fungible_token::events::{FtBurn, FtMint};
+use near_contract_standards::fungible_token::metadata::{
+    FungibleTokenMetadata, FungibleTokenMetadataProvider,
+};
+use near_sdk::{Balance, PromiseOrValue};
+
+//receipt tokens are minted 1:1 against the principal staked in the receipt FT, they stay with the
+//account they are sent to, so whoever unstakes a position has to hold the receipts for it
+impl Contract {
+    pub(crate) fn internal_receipt_balance(&self, account_id: &AccountId) -> u128 {
+        self.receipt_token.accounts.get(account_id).unwrap_or(0)
+    }
+
+    //receipts of a stake go along when the stake changes owner
+    pub(crate) fn internal_move_receipt(&mut self, sender_id: &AccountId, receiver_id: &AccountId, amount: u128) {
+        if amount == 0 {
+            return;
+        }
+        if !self.receipt_token.accounts.contains_key(receiver_id) {
+            self.receipt_token.internal_register_account(receiver_id);
+        }
+        self.receipt_token.internal_transfer(
+            sender_id,
+            receiver_id,
+            amount,
+            Some("Receipt for a transferred stake".to_string()),
+        );
+    }
+
+    pub(crate) fn internal_mint_receipt(
+        &mut self,
+        ft_contract_id: &AccountId,
+        account_id: &AccountId,
+        amount: u128,
+    ) -> u128 {
+        if self.receipt_ft_account_id.as_ref() != Some(ft_contract_id) || amount == 0 {
+            return 0;
+        }
+        if !self.receipt_token.accounts.contains_key(account_id) {
+            self.receipt_token.internal_register_account(account_id);
+        }
+        self.receipt_token.internal_deposit(account_id, amount);
+        FtMint {
+            owner_id: account_id,
+            amount: &U128::from(amount),
+            memo: Some("Receipt for staked tokens"),
+        }
+        .emit();
+        amount
+    }
+
+    pub(crate) fn internal_burn_receipt(&mut self, account_id: &AccountId, amount: u128) {
+        if amount == 0 {
+            return;
+        }
+        self.receipt_token.internal_withdraw(account_id, amount);
+        FtBurn {
+            owner_id: account_id,
+            amount: &U128::from(amount),
+            memo: Some("Receipt for unstaked tokens"),
+        }
+        .emit();
+    }
+
+    fn on_tokens_burned(&mut self, account_id: AccountId, amount: Balance) {
+        log!("Account @{} burned {} receipt tokens", account_id, amount);
+    }
+
+    fn on_account_closed(&mut self, account_id: AccountId, balance: Balance) {
+        //the receipts of the stakes are burned when they are unstaked, closing the account would lock them
+        let has_receipts = self
+            .staking_history
+            .get(&account_id)
+            .is_some_and(|stakes| stakes.iter().any(|stake| u128::from(stake.receipt_amount) > 0));
+        assert!(!has_receipts, "Cannot close the receipt account while it has stakes");
+        log!("Closed @{} with {} receipt tokens", account_id, balance);
+    }
+}
+
+near_contract_standards::impl_fungible_token_core!(Contract, receipt_token, on_tokens_burned);
+near_contract_standards::impl_fungible_token_storage!(Contract, receipt_token, on_account_closed);
+
+#[near_bindgen]
+impl FungibleTokenMetadataProvider for Contract {
+    fn ft_metadata(&self) -> FungibleTokenMetadata {
+        self.receipt_metadata.clone().expect("Receipt token is not configured")
+    }
+}
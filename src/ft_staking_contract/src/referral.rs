@@ -0,0 +1,105 @@
+use crate::*;
+
+//longest chain of referrers above a new referrer that is checked for cycles, the walk stops there
+const MAX_REFERRAL_DEPTH: usize = 20;
+
+//paid by the contract to the referrer of a stake, on top of the staker's own rewards
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ReferralConfig {
+    pub reward_bps: u16, //share of the rewards claimed by referred stakes, Ex: 5% = 500
+    pub flat_bonus: U128, //paid once when the first stake of an account is referred
+}
+
+#[near_bindgen]
+impl Contract {
+    pub fn get_referral_config(&self, ft_account_id: AccountId) -> Option<ReferralConfig> {
+        self.referral_configs.get(&ft_account_id)
+    }
+
+    pub fn get_referrer(&self, account_id: AccountId) -> Option<AccountId> {
+        self.referrers.get(&account_id)
+    }
+
+    pub fn get_referral_rewards(&self, account_id: AccountId, ft_account_id: AccountId) -> U128 {
+        self.referral_rewards
+            .get(&(account_id, ft_account_id))
+            .unwrap_or(U128::from(0))
+    }
+}
+
+impl Contract {
+    //the referrer of an account is set by its first referred stake and kept for the next ones
+    pub(crate) fn internal_set_referrer(
+        &mut self,
+        account_id: &AccountId,
+        ft_account_id: &AccountId,
+        referrer: Option<AccountId>,
+    ) -> Option<AccountId> {
+        let current = self.referrers.get(account_id);
+        let referrer = match referrer {
+            Some(referrer) => referrer,
+            None => return current,
+        };
+        if let Some(current) = current {
+            assert_eq!(
+                current.to_string(),
+                referrer.to_string(),
+                "Account was already referred by another account"
+            );
+            return Some(current);
+        }
+        assert_ne!(account_id, &referrer, "Cannot refer yourself");
+        //the chain of referrers above the new referrer cannot lead back to the account
+        let mut upline = self.referrers.get(&referrer);
+        let mut depth = 0;
+        while let Some(next) = upline {
+            assert_ne!(account_id, &next, "Referral cycles are not allowed");
+            depth += 1;
+            if depth >= MAX_REFERRAL_DEPTH {
+                break;
+            }
+            upline = self.referrers.get(&next);
+        }
+        self.referrers.insert(account_id, &referrer);
+
+        //accounts that staked before being referred did not join through the referrer
+        let first_stake = self.staking_history.get(account_id).is_none();
+        if let Some(config) = self.referral_configs.get(ft_account_id).filter(|_| first_stake) {
+            self.internal_add_referral_reward(&referrer, ft_account_id, u128::from(config.flat_bonus));
+        }
+        log!("{} referred by {}", account_id, referrer);
+        Some(referrer)
+    }
+
+    //accrues the referral share of rewards paid to a stake or a flexible position
+    pub(crate) fn internal_accrue_referral(
+        &mut self,
+        referrer: Option<&AccountId>,
+        ft_account_id: &AccountId,
+        reward: u128,
+    ) {
+        let referrer = match referrer {
+            Some(referrer) => referrer,
+            None => return,
+        };
+        if let Some(config) = self.referral_configs.get(ft_account_id) {
+            let amount = Contract::mul_div(reward, config.reward_bps as u128, 10000);
+            self.internal_add_referral_reward(referrer, ft_account_id, amount);
+        }
+    }
+
+    pub(crate) fn internal_add_referral_reward(
+        &mut self,
+        referrer: &AccountId,
+        ft_account_id: &AccountId,
+        amount: u128,
+    ) {
+        if amount == 0 {
+            return;
+        }
+        let key = (referrer.clone(), ft_account_id.clone());
+        let total = u128::from(self.referral_rewards.get(&key).unwrap_or(U128::from(0))) + amount;
+        self.referral_rewards.insert(&key, &U128::from(total));
+    }
+}
@@ -0,0 +1,285 @@
+use crate::*;
+
+//running totals of a token or of one of its plans
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StakingStats {
+    pub total_staked: U128,
+    pub active_stakes: u64,
+    pub unique_stakers: u64,
+    pub rewards_paid: U128,
+    pub pending_liabilities: U128, //rewards earned by the stakes and not paid yet, estimated per plan
+}
+
+//where the liabilities of a plan were last brought up to date
+#[derive(BorshDeserialize, BorshSerialize, Clone, Debug)]
+pub struct LiabilityCheckpoint {
+    pub updated_at: u64,
+    pub reward_per_share: U128, //of the flexible or emission pool the plan earns from
+    pub weight: U128,           //emission weight of the stakes of the plan
+}
+
+impl StakingStats {
+    pub(crate) fn new() -> Self {
+        Self {
+            total_staked: U128::from(0),
+            active_stakes: 0,
+            unique_stakers: 0,
+            rewards_paid: U128::from(0),
+            pending_liabilities: U128::from(0),
+        }
+    }
+
+    fn add_liabilities(&mut self, amount: u128) {
+        self.pending_liabilities = U128::from(u128::from(self.pending_liabilities) + amount);
+    }
+
+    fn remove_liabilities(&mut self, amount: u128) {
+        self.pending_liabilities = U128::from(u128::from(self.pending_liabilities).saturating_sub(amount));
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    pub fn get_token_stats(&self, ft_account_id: AccountId) -> StakingStats {
+        let mut stats = self.internal_token_stats(&ft_account_id);
+        let plans: Vec<String> = self
+            .approved_fts
+            .get(&ft_account_id)
+            .and_then(|ft| ft.apy_against_duration)
+            .map(|apy_map| apy_map.keys().cloned().collect())
+            .unwrap_or_default();
+        for staking_plan in plans {
+            let plan_stats = self.internal_plan_stats(&ft_account_id, &staking_plan);
+            let (accrued, _) = self.internal_accrued_liabilities(&ft_account_id, &staking_plan, &plan_stats);
+            stats.add_liabilities(accrued);
+        }
+        stats
+    }
+
+    pub fn get_plan_stats(&self, ft_account_id: AccountId, staking_plan: String) -> StakingStats {
+        let mut stats = self.internal_plan_stats(&ft_account_id, &staking_plan);
+        let (accrued, _) = self.internal_accrued_liabilities(&ft_account_id, &staking_plan, &stats);
+        stats.add_liabilities(accrued);
+        stats
+    }
+}
+
+impl Contract {
+    pub(crate) fn internal_plan_stats(&self, ft_account_id: &AccountId, staking_plan: &str) -> StakingStats {
+        self.plan_stats
+            .get(&(ft_account_id.clone(), staking_plan.to_string()))
+            .unwrap_or_else(StakingStats::new)
+    }
+
+    pub(crate) fn internal_token_stats(&self, ft_account_id: &AccountId) -> StakingStats {
+        self.token_stats.get(ft_account_id).unwrap_or_else(StakingStats::new)
+    }
+
+    pub(crate) fn internal_plan_total(&self, ft_account_id: &AccountId, staking_plan: &str) -> u128 {
+        u128::from(self.internal_plan_stats(ft_account_id, staking_plan).total_staked)
+    }
+
+    pub(crate) fn internal_token_total(&self, ft_account_id: &AccountId) -> u128 {
+        u128::from(self.internal_token_stats(ft_account_id).total_staked)
+    }
+
+    //applies the same change to the stats of a plan and of its token
+    fn internal_update_stats<F: Fn(&mut StakingStats)>(
+        &mut self,
+        ft_account_id: &AccountId,
+        staking_plan: &str,
+        update: F,
+    ) {
+        let mut plan_stats = self.internal_plan_stats(ft_account_id, staking_plan);
+        update(&mut plan_stats);
+        self.plan_stats
+            .insert(&(ft_account_id.clone(), staking_plan.to_string()), &plan_stats);
+        let mut token_stats = self.internal_token_stats(ft_account_id);
+        update(&mut token_stats);
+        self.token_stats.insert(ft_account_id, &token_stats);
+    }
+
+    //rewards the stakes of a plan earned since its checkpoint, with the checkpoint moved to now
+    pub(crate) fn internal_accrued_liabilities(
+        &self,
+        ft_account_id: &AccountId,
+        staking_plan: &str,
+        stats: &StakingStats,
+    ) -> (u128, LiabilityCheckpoint) {
+        let apy = self.internal_get_apy(ft_account_id, staking_plan);
+        let current_time = env::block_timestamp() / 1000000000;
+        let reward_per_share = match apy.plan_type {
+            PlanType::Fixed => 0,
+            PlanType::Flexible => {
+                let (pool, _) = self.internal_get_pool(ft_account_id, staking_plan);
+                u128::from(pool.reward_per_share)
+            }
+            PlanType::Emission => u128::from(self.internal_get_emission_pool(ft_account_id).reward_per_share),
+        };
+        let mut checkpoint = self
+            .plan_checkpoints
+            .get(&(ft_account_id.clone(), staking_plan.to_string()))
+            .unwrap_or(LiabilityCheckpoint {
+                updated_at: current_time,
+                reward_per_share: U128::from(reward_per_share),
+                weight: U128::from(0),
+            });
+        //a plan moved to another pool type starts again from the rate of its new pool
+        let growth = reward_per_share.saturating_sub(u128::from(checkpoint.reward_per_share));
+        let accrued = match apy.plan_type {
+            PlanType::Fixed => {
+                //whole minutes only, like the rewards of the stakes
+                let periods = (current_time - checkpoint.updated_at) / ONE_MINUTE;
+                checkpoint.updated_at += periods * ONE_MINUTE;
+                let total_staked = u128::from(stats.total_staked);
+                //compounding stakes earn on their rewards too, the plan total is compounded the same way
+                if apy.auto_compound {
+                    Contract::compound_amount(total_staked, &apy, periods).expect("Compounded amount overflows")
+                        - total_staked
+                } else {
+                    Contract::simple_reward(total_staked, &apy, periods)
+                }
+            }
+            PlanType::Flexible => {
+                checkpoint.updated_at = current_time;
+                Contract::mul_div(u128::from(stats.total_staked), growth, REWARD_PRECISION)
+            }
+            PlanType::Emission => {
+                checkpoint.updated_at = current_time;
+                Contract::mul_div(u128::from(checkpoint.weight), growth, REWARD_PRECISION)
+            }
+        };
+        checkpoint.reward_per_share = U128::from(reward_per_share);
+        (accrued, checkpoint)
+    }
+
+    //brings the pending liabilities of a plan and its token up to now, before the plan changes
+    pub(crate) fn internal_accrue_liabilities(&mut self, ft_account_id: &AccountId, staking_plan: &str) {
+        let stats = self.internal_plan_stats(ft_account_id, staking_plan);
+        let (accrued, checkpoint) = self.internal_accrued_liabilities(ft_account_id, staking_plan, &stats);
+        self.plan_checkpoints
+            .insert(&(ft_account_id.clone(), staking_plan.to_string()), &checkpoint);
+        if accrued > 0 {
+            self.internal_update_stats(ft_account_id, staking_plan, |stats| stats.add_liabilities(accrued));
+        }
+    }
+
+    pub(crate) fn internal_add_staked(&mut self, ft_account_id: &AccountId, staking_plan: &str, amount: u128) {
+        self.internal_accrue_liabilities(ft_account_id, staking_plan);
+        self.internal_update_stats(ft_account_id, staking_plan, |stats| {
+            stats.total_staked = U128::from(u128::from(stats.total_staked) + amount);
+        });
+    }
+
+    pub(crate) fn internal_remove_staked(&mut self, ft_account_id: &AccountId, staking_plan: &str, amount: u128) {
+        self.internal_accrue_liabilities(ft_account_id, staking_plan);
+        self.internal_update_stats(ft_account_id, staking_plan, |stats| {
+            stats.total_staked = U128::from(u128::from(stats.total_staked).saturating_sub(amount));
+        });
+    }
+
+    //emission weight the stakes of a plan earn with
+    pub(crate) fn internal_change_plan_weight(
+        &mut self,
+        ft_account_id: &AccountId,
+        staking_plan: &str,
+        weight: u128,
+        added: bool,
+    ) {
+        self.internal_accrue_liabilities(ft_account_id, staking_plan);
+        let key = (ft_account_id.clone(), staking_plan.to_string());
+        let mut checkpoint = self.plan_checkpoints.get(&key).unwrap();
+        let total = u128::from(checkpoint.weight);
+        checkpoint.weight = U128::from(if added { total + weight } else { total.saturating_sub(weight) });
+        self.plan_checkpoints.insert(&key, &checkpoint);
+    }
+
+    //rewards that are no longer owed, either paid out or forfeited
+    pub(crate) fn internal_release_rewards(
+        &mut self,
+        ft_account_id: &AccountId,
+        staking_plan: &str,
+        amount: u128,
+        paid: bool,
+    ) {
+        if amount == 0 {
+            return;
+        }
+        self.internal_accrue_liabilities(ft_account_id, staking_plan);
+        self.internal_update_stats(ft_account_id, staking_plan, |stats| {
+            stats.remove_liabilities(amount);
+            if paid {
+                stats.rewards_paid = U128::from(u128::from(stats.rewards_paid) + amount);
+            }
+        });
+    }
+
+    //counts a stake of an account in or out of its plan and token
+    pub(crate) fn internal_track_stake(
+        &mut self,
+        account_id: &AccountId,
+        ft_account_id: &AccountId,
+        staking_plan: &str,
+        added: bool,
+    ) {
+        let plan_key = (account_id.clone(), ft_account_id.clone(), staking_plan.to_string());
+        let plan_count = self.account_plan_stakes.get(&plan_key).unwrap_or(0);
+        let token_key = (account_id.clone(), ft_account_id.clone());
+        let token_count = self.account_token_stakes.get(&token_key).unwrap_or(0);
+        if !added && plan_count == 0 {
+            return;
+        }
+        let step = |count: u64| if added { count + 1 } else { count - 1 };
+        let (new_plan_count, new_token_count) = (step(plan_count), step(token_count));
+        if new_plan_count > 0 {
+            self.account_plan_stakes.insert(&plan_key, &new_plan_count);
+        } else {
+            self.account_plan_stakes.remove(&plan_key);
+        }
+        if new_token_count > 0 {
+            self.account_token_stakes.insert(&token_key, &new_token_count);
+        } else {
+            self.account_token_stakes.remove(&token_key);
+        }
+
+        //an account is a new staker with its first stake and leaves with its last one
+        let mut plan_stats = self.internal_plan_stats(ft_account_id, staking_plan);
+        plan_stats.active_stakes = step(plan_stats.active_stakes);
+        if plan_count == 0 || new_plan_count == 0 {
+            plan_stats.unique_stakers = step(plan_stats.unique_stakers);
+        }
+        self.plan_stats
+            .insert(&(ft_account_id.clone(), staking_plan.to_string()), &plan_stats);
+        let mut token_stats = self.internal_token_stats(ft_account_id);
+        token_stats.active_stakes = step(token_stats.active_stakes);
+        if token_count == 0 || new_token_count == 0 {
+            token_stats.unique_stakers = step(token_stats.unique_stakers);
+        }
+        self.token_stats.insert(ft_account_id, &token_stats);
+    }
+
+    //moves a stake, with the rewards it is still owed, to another plan of its token
+    pub(crate) fn internal_move_stake_plan(&mut self, account_id: &AccountId, stake: &Stake, new_plan: &str) {
+        if stake.staking_plan == new_plan {
+            return;
+        }
+        let ft_account_id = &stake.ft_account_id;
+        self.internal_track_stake(account_id, ft_account_id, &stake.staking_plan, false);
+        self.internal_track_stake(account_id, ft_account_id, new_plan, true);
+        let owed = u128::from(stake.unclaimed_reward);
+        if owed == 0 {
+            return;
+        }
+        self.internal_accrue_liabilities(ft_account_id, &stake.staking_plan);
+        self.internal_accrue_liabilities(ft_account_id, new_plan);
+        let mut old_stats = self.internal_plan_stats(ft_account_id, &stake.staking_plan);
+        old_stats.remove_liabilities(owed);
+        self.plan_stats
+            .insert(&(ft_account_id.clone(), stake.staking_plan.clone()), &old_stats);
+        let mut new_stats = self.internal_plan_stats(ft_account_id, new_plan);
+        new_stats.add_liabilities(owed);
+        self.plan_stats
+            .insert(&(ft_account_id.clone(), new_plan.to_string()), &new_stats);
+    }
+}
@@ -0,0 +1,127 @@
+use crate::*;
+use std::cmp::max;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StakeSummary {
+    pub stake_id: StakeId,
+    pub staking_plan: String,
+    pub amount: U128,
+    pub pending_reward: U128,
+    pub claimable_reward: U128, //what claim_reward would pay now
+    pub unlocks_at: u64,
+}
+
+//everything an account has in one token, flexible positions are counted in the totals
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TokenSummary {
+    pub ft_account_id: AccountId,
+    pub staked: U128,
+    pub claimable: U128,
+    pub claimed: U128,
+    pub locked_until: Option<u64>,   //when the last locked stake unlocks
+    pub next_unlock_at: Option<u64>, //when the next locked stake unlocks
+    pub stakes: Vec<StakeSummary>,
+}
+
+impl TokenSummary {
+    fn new(ft_account_id: AccountId, claimed: U128) -> Self {
+        Self {
+            ft_account_id,
+            staked: U128::from(0),
+            claimable: U128::from(0),
+            claimed,
+            locked_until: None,
+            next_unlock_at: None,
+            stakes: Vec::new(),
+        }
+    }
+
+    fn add(&mut self, staked: u128, claimable: u128) {
+        self.staked = U128::from(u128::from(self.staked) + staked);
+        self.claimable = U128::from(u128::from(self.claimable) + claimable);
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    pub fn get_account_summary(&self, account_id: AccountId) -> Vec<TokenSummary> {
+        let current_time = env::block_timestamp() / 1000000000;
+        let denied = self.denylist.contains(&account_id);
+        let stakes = self.staking_history.get(&account_id).unwrap_or_default();
+        let unbonding = self.unbonding.get(&account_id).unwrap_or_default();
+        let mut summaries: Vec<TokenSummary> = Vec::new();
+        for ft_account_id in self.approved_ft_token_ids.iter() {
+            let claimed = self.internal_claimed_rewards(&account_id, &ft_account_id);
+            let mut summary = TokenSummary::new(ft_account_id.clone(), claimed);
+            for stake in stakes.iter().filter(|stake| stake.ft_account_id == ft_account_id) {
+                let claimable = self.internal_claimable_reward(stake, current_time);
+                let unlocks_at = Contract::unlocks_at(stake, current_time);
+                if unlocks_at > current_time {
+                    summary.locked_until = Some(max(summary.locked_until.unwrap_or(0), unlocks_at));
+                    summary.next_unlock_at =
+                        Some(min(summary.next_unlock_at.unwrap_or(u64::MAX), unlocks_at));
+                }
+                summary.add(u128::from(stake.amount), claimable);
+                summary.stakes.push(StakeSummary {
+                    stake_id: stake.stake_id,
+                    staking_plan: stake.staking_plan.clone(),
+                    amount: stake.amount,
+                    pending_reward: U128::from(self.internal_pending_reward(stake)),
+                    claimable_reward: U128::from(claimable),
+                    unlocks_at,
+                });
+            }
+
+            //unbonding stakes are still staked, their rewards are paid with withdraw_unbonded
+            for entry in unbonding.iter().filter(|entry| entry.stake.ft_account_id == ft_account_id) {
+                summary.add(u128::from(entry.stake.amount), 0);
+            }
+
+            //flexible positions are not locked, their rewards are claimed with flexible_claim
+            let plans: Vec<APY> = self
+                .approved_fts
+                .get(&ft_account_id)
+                .and_then(|ft| ft.apy_against_duration)
+                .map(|apy_map| apy_map.values().cloned().collect())
+                .unwrap_or_default();
+            for apy in plans.iter().filter(|apy| apy.plan_type == PlanType::Flexible) {
+                let (pool, _) = self.internal_get_pool(&ft_account_id, &apy.apy_key);
+                let mut position = self.internal_get_position(&account_id, &ft_account_id, &apy.apy_key);
+                position.settle(&pool);
+                let claimable = if denied { 0 } else { u128::from(position.pending_reward) };
+                summary.add(u128::from(position.amount), claimable);
+            }
+
+            //tokens the account never used are left out
+            if u128::from(summary.staked) > 0
+                || u128::from(summary.claimable) > 0
+                || u128::from(summary.claimed) > 0
+            {
+                summaries.push(summary);
+            }
+        }
+        summaries
+    }
+}
+
+impl Contract {
+    pub(crate) fn internal_claimed_rewards(&self, account_id: &AccountId, ft_account_id: &AccountId) -> U128 {
+        self.claimed_rewards
+            .get(&(account_id.clone(), ft_account_id.clone()))
+            .unwrap_or(U128::from(0))
+    }
+
+    //rewards paid to an account, with claims or together with the principal
+    pub(crate) fn internal_add_claimed(
+        &mut self,
+        account_id: &AccountId,
+        ft_account_id: &AccountId,
+        amount: u128,
+    ) {
+        let claimed = u128::from(self.internal_claimed_rewards(account_id, ft_account_id)) + amount;
+        self.claimed_rewards
+            .insert(&(account_id.clone(), ft_account_id.clone()), &U128::from(claimed));
+    }
+}
@@ -0,0 +1,83 @@
+use crate::*;
+
+//a membership level reached with min_staked whole tokens staked across all FTs
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Tier {
+    pub name: String,
+    pub min_staked: U128,
+}
+
+#[near_bindgen]
+impl Contract {
+    pub fn get_tiers(&self) -> Vec<Tier> {
+        self.tiers.clone()
+    }
+
+    pub fn get_tier(&self, account_id: AccountId) -> Option<Tier> {
+        self.internal_tier_rank(&account_id).map(|rank| self.tiers[rank].clone())
+    }
+
+    pub fn get_airdrop_tier(&self, ft_contract_id: AccountId) -> Option<String> {
+        self.airdrop_tiers.get(&ft_contract_id)
+    }
+}
+
+impl Contract {
+    //principal of an account in whole tokens, so FTs with different decimals can be added up,
+    //locked, unbonding and flexible positions all count
+    pub(crate) fn internal_total_staked(&self, account_id: &AccountId) -> u128 {
+        let stakes = self.staking_history.get(account_id).unwrap_or_default();
+        let unbonding = self.unbonding.get(account_id).unwrap_or_default();
+        let mut total: u128 = 0;
+        for ft in self.approved_ft_token_ids.iter().filter_map(|id| self.approved_fts.get(&id)) {
+            let decimals = match ft.decimals {
+                Some(decimals) => decimals,
+                None => continue,
+            };
+            let mut staked: u128 = stakes
+                .iter()
+                .chain(unbonding.iter().map(|entry| &entry.stake))
+                .filter(|stake| stake.ft_account_id == ft.account_id)
+                .map(|stake| u128::from(stake.amount))
+                .sum();
+            let flexible_plans = ft
+                .apy_against_duration
+                .iter()
+                .flat_map(|apy_map| apy_map.values())
+                .filter(|apy| apy.plan_type == PlanType::Flexible);
+            for apy in flexible_plans {
+                staked += u128::from(self.internal_get_position(account_id, &ft.account_id, &apy.apy_key).amount);
+            }
+            total += staked / 10u128.pow(decimals as u32);
+        }
+        total
+    }
+
+    //index of the highest tier reached by an account, tiers are sorted from the lowest
+    pub(crate) fn internal_tier_rank(&self, account_id: &AccountId) -> Option<usize> {
+        let total_staked = self.internal_total_staked(account_id);
+        self.tiers
+            .iter()
+            .rposition(|tier| total_staked >= u128::from(tier.min_staked))
+    }
+
+    pub(crate) fn assert_tier(&self, account_id: &AccountId, min_tier: &str) {
+        let required = self
+            .tiers
+            .iter()
+            .position(|tier| tier.name == min_tier)
+            .expect("Unknown tier");
+        assert!(
+            self.internal_tier_rank(account_id).is_some_and(|rank| rank >= required),
+            "Only {} members and above are eligible",
+            min_tier
+        );
+    }
+
+    pub(crate) fn assert_plan_eligible(&self, account_id: &AccountId, apy: &APY) {
+        if let Some(min_tier) = &apy.min_tier {
+            self.assert_tier(account_id, min_tier);
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use crate::*;
+use crate::pool::SECONDS_PER_YEAR;
+
+//voting power of the stakes an account has in one token, from timestamp until its next checkpoint
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct VotingCheckpoint {
+    pub timestamp: u64,
+    pub power: U128,
+    pub renewing_power: U128, //of the auto renewing locks, it does not run down
+    //end of each running lock with the amount that stops losing a vote per token and year then, by time
+    pub slope_changes: Vec<(u64, U128)>,
+}
+
+impl VotingCheckpoint {
+    //amount times the remaining lock time, a token locked for one year is one vote
+    pub(crate) fn from_stakes<'a>(timestamp: u64, stakes: impl Iterator<Item = &'a Stake>) -> Self {
+        let mut renewing_power: u128 = 0;
+        let mut slope_changes: Vec<(u64, U128)> = Vec::new();
+        for stake in stakes {
+            let amount = u128::from(stake.amount);
+            //auto renewing locks are locked again at the end of each cycle and keep their whole duration
+            if stake.auto_renew {
+                renewing_power += Contract::mul_div(amount, stake.duration as u128, SECONDS_PER_YEAR);
+                continue;
+            }
+            let ends_at = stake.staked_at + stake.duration;
+            if ends_at <= timestamp {
+                continue;
+            }
+            match slope_changes.binary_search_by_key(&ends_at, |change| change.0) {
+                Ok(index) => slope_changes[index].1 = U128::from(u128::from(slope_changes[index].1) + amount),
+                Err(index) => slope_changes.insert(index, (ends_at, U128::from(amount))),
+            }
+        }
+        let mut checkpoint = Self {
+            timestamp,
+            power: U128::from(0),
+            renewing_power: U128::from(renewing_power),
+            slope_changes,
+        };
+        checkpoint.power = U128::from(checkpoint.power_at(timestamp));
+        checkpoint
+    }
+
+    //every lock loses its power until its own end, so the power is exact at any time after the checkpoint
+    pub(crate) fn power_at(&self, timestamp: u64) -> u128 {
+        let timestamp = timestamp.max(self.timestamp);
+        let running: u128 = self
+            .slope_changes
+            .iter()
+            .filter(|(ends_at, _)| *ends_at > timestamp)
+            .map(|(ends_at, amount)| {
+                Contract::mul_div(u128::from(*amount), (ends_at - timestamp) as u128, SECONDS_PER_YEAR)
+            })
+            .sum();
+        u128::from(self.renewing_power) + running
+    }
+
+    //the locks that are still running at a time
+    fn running_at(&self, timestamp: u64) -> &[(u64, U128)] {
+        let index = self.slope_changes.partition_point(|(ends_at, _)| *ends_at <= timestamp);
+        &self.slope_changes[index..]
+    }
+}
+
+impl Contract {
+    //saves the stakes of an account and records the voting power of each token they changed
+    pub(crate) fn internal_save_stakes(&mut self, account_id: &AccountId, stakes: &Vec<Stake>) {
+        let previous = self.staking_history.insert(account_id, stakes).unwrap_or_default();
+
+        let current_time = env::block_timestamp() / 1000000000;
+        let mut tokens: Vec<AccountId> = Vec::new();
+        for stake in previous.iter().chain(stakes.iter()) {
+            if !tokens.contains(&stake.ft_account_id) {
+                tokens.push(stake.ft_account_id.clone());
+            }
+        }
+        for ft_account_id in tokens {
+            let checkpoint = VotingCheckpoint::from_stakes(
+                current_time,
+                stakes.iter().filter(|stake| stake.ft_account_id == ft_account_id),
+            );
+            let key = (account_id.clone(), ft_account_id);
+            let mut checkpoints = self.voting_checkpoints.get(&key).unwrap_or_default();
+            if let Some(last) = checkpoints.last() {
+                //nothing to record when the locks of this token did not change
+                if last.renewing_power == checkpoint.renewing_power
+                    && last.running_at(current_time) == checkpoint.slope_changes.as_slice()
+                {
+                    continue;
+                }
+                //several changes in the same block keep only the last one
+                if last.timestamp == current_time {
+                    checkpoints.pop();
+                }
+            }
+            checkpoints.push(checkpoint);
+            self.voting_checkpoints.insert(&key, &checkpoints);
+        }
+    }
+
+    //voting power of the stakes an account has in one token
+    pub(crate) fn internal_voting_power(&self, account_id: &AccountId, ft_account_id: &AccountId, timestamp: u64) -> u128 {
+        let checkpoints = self
+            .voting_checkpoints
+            .get(&(account_id.clone(), ft_account_id.clone()))
+            .unwrap_or_default();
+        let index = checkpoints.partition_point(|checkpoint| checkpoint.timestamp <= timestamp);
+        if index == 0 {
+            return 0;
+        }
+        checkpoints[index - 1].power_at(timestamp)
+    }
+}
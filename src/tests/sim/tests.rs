@@ -364,7 +364,7 @@ pub fn check_stake_id_for_un_staking() {
     assert_eq!(amount, staking_balance.0);
     let id: U128 = U128::from(2);
 
-    call!(alice, staking.ft_unstake(id)).assert_success();
+    call!(alice, staking.ft_unstake(id, None)).assert_success();
 }
 
 #[test]
@@ -393,7 +393,7 @@ pub fn check_who_can_unstake() {
     assert_eq!(amount + amount, staking_balance.0);
     let id: U128 = U128::from(1);
 
-    call!(root, staking.ft_unstake(id)).assert_success();
+    call!(root, staking.ft_unstake(id, None)).assert_success();
 }
 
 #[test]
@@ -420,5 +420,51 @@ pub fn check_duration_of_unstaking() {
     assert_eq!(amount, staking_balance.0);
     let id: U128 = U128::from(1);
     //Time duration will no meet
-    call!(alice, staking.ft_unstake(id)).assert_success();
-}
\ No newline at end of file
+    call!(alice, staking.ft_unstake(id, None)).assert_success();
+}
+#[test]
+#[should_panic(expected = "Cannot split more than")]
+pub fn check_split_stake_amounts() {
+    let amount = to_yocto("6000");
+    let initial_balance = to_yocto("6000");
+    let (root, ft, staking, alice) = init(initial_balance);
+
+    register_user(&staking.user_account);
+    call!(
+        root,
+        ft.ft_transfer(alice.account_id(), to_yocto("6000").into(), None),
+        deposit = 1
+    )
+    .assert_success();
+    call!(alice,ft.ft_transfer_call(staking.account_id(),amount.into(),None,"{\"ft_symbol\":\"UNCT\",\"ft_account_id\":\"ft\",\"decimal\":24,\"duration\":15778800,\"staked_by\":\"alice\",\"staking_plan\":\"3months\"}".to_string()),
+    deposit =1).assert_success();
+
+    let id: U128 = U128::from(1);
+    let amounts: Vec<U128> = vec![to_yocto("3000").into(), to_yocto("3000").into()];
+    call!(alice, staking.split_stake(id, amounts)).assert_success();
+}
+
+#[test]
+#[should_panic(expected = "No staking data with this id found for caller")]
+pub fn check_transferred_stake_owner() {
+    let amount = to_yocto("6000");
+    let initial_balance = to_yocto("6000");
+    let (root, ft, staking, alice) = init(initial_balance);
+
+    register_user(&staking.user_account);
+    call!(
+        root,
+        ft.ft_transfer(alice.account_id(), to_yocto("6000").into(), None),
+        deposit = 1
+    )
+    .assert_success();
+    call!(alice,ft.ft_transfer_call(staking.account_id(),amount.into(),None,"{\"ft_symbol\":\"UNCT\",\"ft_account_id\":\"ft\",\"decimal\":24,\"duration\":15778800,\"staked_by\":\"alice\",\"staking_plan\":\"3months\"}".to_string()),
+    deposit =1).assert_success();
+
+    let id: U128 = U128::from(1);
+    call!(alice, staking.transfer_stake(id, root.account_id()), deposit = 1).assert_success();
+
+    let ten_millis = time::Duration::from_secs(10);
+    thread::sleep(ten_millis);
+    call!(alice, staking.claim_reward(id)).assert_success();
+}
@@ -54,24 +54,16 @@ pub fn init(
     let alice = root.create_user("alice".parse().unwrap(), to_yocto("1000"));
     register_user(&alice);
     let apy_data: Vec<APY> = [
-        APY {
-            apy_key: "3months".to_string(),
-            interest_rate: 250,
-            min_duration: 3,
-            min_staking_amount: U128::from(500000000000000000000000000),
-        },
-        APY {
-            apy_key: "6months".to_string(),
-            interest_rate: 500,
-            min_duration: 6,
-            min_staking_amount: U128::from(5000000000000000000000000000),
-        },
+        APY::new("3months".to_string(), U128::from(500000000000000000000000000), 3, 250),
+        APY::new("6months".to_string(), U128::from(5000000000000000000000000000), 6, 500),
     ]
     .to_vec();
     let approved_fts: Vec<FT> = [FT {
         account_id: ft.account_id(),
         symbol: "UNCT".to_string(),
         apy_against_duration: None,
+        max_total_staked: None,
+        decimals: Some(24),
     }]
     .to_vec();
     let staking = deploy!(